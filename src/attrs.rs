@@ -0,0 +1,77 @@
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Meta, Path, Token};
+
+/// `#[derive(...)]`で導出されるtraitの名前を集める
+///
+/// `#[cfg_attr(..., derive(...))]`の中にあるものも含める
+pub(crate) fn derived_traits(attrs: &[Attribute]) -> Vec<String> {
+  let mut v = Vec::new();
+  for attr in attrs.iter() {
+    collect_derives(&attr.meta, &mut v);
+  }
+  v
+}
+
+fn collect_derives(meta: &Meta, v: &mut Vec<String>) {
+  let Meta::List(list) = meta else {
+    return;
+  };
+  if list.path.is_ident("derive") {
+    if let Ok(paths) = list.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated) {
+      for path in paths.iter() {
+        if let Some(segment) = path.segments.last() {
+          let name = segment.ident.to_string();
+          if !v.contains(&name) {
+            v.push(name);
+          }
+        }
+      }
+    }
+  } else if list.path.is_ident("cfg_attr") {
+    // 最初の要素は条件で、残りが属性になる
+    if let Ok(metas) = list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated) {
+      for meta in metas.iter().skip(1) {
+        collect_derives(meta, v);
+      }
+    }
+  }
+}
+
+/// `#[non_exhaustive]`が付いているか
+pub(crate) fn is_non_exhaustive(attrs: &[Attribute]) -> bool {
+  attrs
+    .iter()
+    .any(|attr| attr.path().is_ident("non_exhaustive"))
+}
+
+/// `#[path = "..."]`で指定されたファイルのパス
+pub(crate) fn path_attr(attrs: &[Attribute]) -> Option<String> {
+  attrs.iter().find_map(|attr| {
+    let Meta::NameValue(name_value) = &attr.meta else {
+      return None;
+    };
+    if !name_value.path.is_ident("path") {
+      return None;
+    }
+    match &name_value.value {
+      syn::Expr::Lit(syn::ExprLit {
+        lit: syn::Lit::Str(s),
+        ..
+      }) => Some(s.value()),
+      _ => None,
+    }
+  })
+}
+
+#[test]
+fn check_derived_traits() {
+  let item: syn::ItemStruct = syn::parse_quote! {
+    #[derive(Clone, std::fmt::Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), cfg_attr(test, derive(Default)))]
+    pub struct Foo;
+  };
+  assert_eq!(
+    vec!["Clone", "Debug", "Serialize", "Default"],
+    derived_traits(&item.attrs)
+  );
+}
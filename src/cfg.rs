@@ -0,0 +1,446 @@
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Fields, ImplItem, Item, Meta, Token, TraitItem};
+
+/// ターゲットごとに値が1つだけ決まる設定
+const SINGLE_VALUED: [&str; 7] = [
+  "target_os",
+  "target_family",
+  "target_arch",
+  "target_env",
+  "target_vendor",
+  "target_pointer_width",
+  "target_endian",
+];
+
+/// `unix`ファミリーに属する主なOS
+const UNIX_OS: [&str; 14] = [
+  "linux",
+  "android",
+  "macos",
+  "ios",
+  "tvos",
+  "watchos",
+  "visionos",
+  "freebsd",
+  "netbsd",
+  "openbsd",
+  "dragonfly",
+  "solaris",
+  "illumos",
+  "haiku",
+];
+
+/// `#[cfg(...)]`の評価に使う設定
+///
+/// `feature = "x"`のような名前と値の組と、`unix`のような名前だけのものを持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CfgSet {
+  options: Vec<(String, Option<String>)>,
+}
+
+impl CfgSet {
+  /// 実行しているホストのターゲットの設定
+  ///
+  /// `test`と`doc`は含まない。`debug_assertions`と`panic = "unwind"`は、
+  /// `cargo build`の既定のプロファイルに合わせて含める
+  pub(crate) fn host() -> Self {
+    let mut cfg = CfgSet {
+      options: Vec::new(),
+    };
+    cfg.insert("target_os", Some(std::env::consts::OS));
+    cfg.insert("target_family", Some(std::env::consts::FAMILY));
+    cfg.insert("target_arch", Some(std::env::consts::ARCH));
+    cfg.insert("target_pointer_width", Some(&usize::BITS.to_string()));
+    cfg.insert(std::env::consts::FAMILY, None);
+    let endian = if cfg!(target_endian = "little") {
+      "little"
+    } else {
+      "big"
+    };
+    cfg.insert("target_endian", Some(endian));
+    let atomics = [
+      ("8", cfg!(target_has_atomic = "8")),
+      ("16", cfg!(target_has_atomic = "16")),
+      ("32", cfg!(target_has_atomic = "32")),
+      ("64", cfg!(target_has_atomic = "64")),
+      ("128", cfg!(target_has_atomic = "128")),
+      ("ptr", cfg!(target_has_atomic = "ptr")),
+    ];
+    for (width, _) in atomics.iter().filter(|(_, has)| *has) {
+      cfg.insert("target_has_atomic", Some(width));
+    }
+    let envs = [
+      ("gnu", cfg!(target_env = "gnu")),
+      ("musl", cfg!(target_env = "musl")),
+      ("msvc", cfg!(target_env = "msvc")),
+      ("sgx", cfg!(target_env = "sgx")),
+      ("uclibc", cfg!(target_env = "uclibc")),
+      ("newlib", cfg!(target_env = "newlib")),
+    ];
+    let env = envs.iter().find(|(_, is)| *is).map_or("", |(env, _)| env);
+    cfg.insert("target_env", Some(env));
+    let vendors = [
+      ("apple", cfg!(target_vendor = "apple")),
+      ("pc", cfg!(target_vendor = "pc")),
+      ("fortanix", cfg!(target_vendor = "fortanix")),
+      ("uwp", cfg!(target_vendor = "uwp")),
+    ];
+    let vendor = vendors
+      .iter()
+      .find(|(_, is)| *is)
+      .map_or("unknown", |(vendor, _)| vendor);
+    cfg.insert("target_vendor", Some(vendor));
+    cfg.insert("debug_assertions", None);
+    cfg.insert("panic", Some("unwind"));
+    cfg
+  }
+
+  /// 設定を追加する
+  pub(crate) fn insert(&mut self, name: &str, value: Option<&str>) {
+    let option = (name.to_string(), value.map(str::to_string));
+    if !self.options.contains(&option) {
+      self.options.push(option);
+    }
+  }
+
+  /// `name`や`name="value"`の形で書かれた設定を追加する
+  ///
+  /// `target_os`のように値が1つだけ決まる設定は、ホストの値を置き換える。
+  /// `target_os`と`target_family`は、`unix`や`windows`も合わせて置き換える
+  pub(crate) fn insert_spec(&mut self, spec: &str) {
+    let Some((name, value)) = spec.split_once('=') else {
+      self.insert(spec.trim(), None);
+      return;
+    };
+    let (name, value) = (name.trim(), value.trim().trim_matches('"'));
+    if SINGLE_VALUED.contains(&name) {
+      self.options.retain(|(n, _)| n != name);
+    }
+    match name {
+      "target_os" => {
+        let family = if value == "windows" {
+          Some("windows")
+        } else if UNIX_OS.contains(&value) {
+          Some("unix")
+        } else {
+          None
+        };
+        self.set_family(family);
+      }
+      "target_family" => {
+        self.set_family(Some(value));
+        return;
+      }
+      _ => (),
+    }
+    self.insert(name, Some(value));
+  }
+
+  /// `target_family`と、`unix`や`windows`を置き換える
+  fn set_family(&mut self, family: Option<&str>) {
+    self
+      .options
+      .retain(|(n, v)| n != "target_family" && !(v.is_none() && (n == "unix" || n == "windows")));
+    if let Some(family) = family {
+      self.insert("target_family", Some(family));
+      if family == "unix" || family == "windows" {
+        self.insert(family, None);
+      }
+    }
+  }
+
+  /// `feature = "name"`を追加する
+  pub(crate) fn enable_feature(&mut self, name: &str) {
+    self.insert("feature", Some(name));
+  }
+
+  fn contains(&self, name: &str, value: Option<&str>) -> bool {
+    self
+      .options
+      .iter()
+      .any(|(n, v)| n == name && v.as_deref() == value)
+  }
+
+  /// `cfg`の条件を評価する
+  fn eval(&self, meta: &Meta) -> bool {
+    match meta {
+      Meta::Path(path) => path
+        .get_ident()
+        .is_some_and(|ident| self.contains(&ident.to_string(), None)),
+      Meta::NameValue(name_value) => {
+        let Some(ident) = name_value.path.get_ident() else {
+          return false;
+        };
+        match &name_value.value {
+          syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(s),
+            ..
+          }) => self.contains(&ident.to_string(), Some(&s.value())),
+          _ => false,
+        }
+      }
+      Meta::List(list) => {
+        let Ok(metas) = list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        else {
+          return false;
+        };
+        if list.path.is_ident("all") {
+          metas.iter().all(|meta| self.eval(meta))
+        } else if list.path.is_ident("any") {
+          metas.iter().any(|meta| self.eval(meta))
+        } else if list.path.is_ident("not") {
+          metas.len() == 1 && !self.eval(&metas[0])
+        } else {
+          false
+        }
+      }
+    }
+  }
+
+  /// `#[cfg(...)]`が全て満たされているか
+  ///
+  /// 読めない条件は満たされているものとして扱う
+  pub(crate) fn is_enabled(&self, attrs: &[Attribute]) -> bool {
+    attrs.iter().all(|attr| {
+      let Meta::List(list) = &attr.meta else {
+        return true;
+      };
+      if !list.path.is_ident("cfg") {
+        return true;
+      }
+      list
+        .parse_args::<Meta>()
+        .map(|meta| self.eval(&meta))
+        .unwrap_or(true)
+    })
+  }
+
+  /// `#[cfg_attr(...)]`を展開し、`#[cfg(...)]`が満たされているかを返す
+  fn process_attrs(&self, attrs: &mut Vec<Attribute>) -> bool {
+    let mut v = Vec::new();
+    for attr in attrs.drain(..) {
+      self.expand_cfg_attr(attr, &mut v);
+    }
+    *attrs = v;
+    self.is_enabled(attrs)
+  }
+
+  fn expand_cfg_attr(&self, attr: Attribute, v: &mut Vec<Attribute>) {
+    let Meta::List(list) = &attr.meta else {
+      v.push(attr);
+      return;
+    };
+    if !list.path.is_ident("cfg_attr") {
+      v.push(attr);
+      return;
+    }
+    let Ok(metas) = list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated) else {
+      v.push(attr);
+      return;
+    };
+    // 最初の要素は条件で、残りが属性になる
+    let mut metas = metas.into_iter();
+    let Some(predicate) = metas.next() else {
+      return;
+    };
+    if !self.eval(&predicate) {
+      return;
+    }
+    for meta in metas {
+      let attr = Attribute {
+        pound_token: attr.pound_token,
+        style: attr.style,
+        bracket_token: attr.bracket_token,
+        meta,
+      };
+      self.expand_cfg_attr(attr, v);
+    }
+  }
+
+  /// `cfg`で除外されるアイテムを取り除く
+  ///
+  /// フィールドやバリアント、implやtraitの中のアイテムも対象にする
+  pub(crate) fn strip_items(&self, items: &mut Vec<Item>) {
+    items.retain_mut(|item| {
+      let attrs = match item {
+        Item::Const(item) => &mut item.attrs,
+        Item::Enum(item) => &mut item.attrs,
+        Item::ExternCrate(item) => &mut item.attrs,
+        Item::Fn(item) => &mut item.attrs,
+        Item::ForeignMod(item) => &mut item.attrs,
+        Item::Impl(item) => &mut item.attrs,
+        Item::Macro(item) => &mut item.attrs,
+        Item::Mod(item) => &mut item.attrs,
+        Item::Static(item) => &mut item.attrs,
+        Item::Struct(item) => &mut item.attrs,
+        Item::Trait(item) => &mut item.attrs,
+        Item::TraitAlias(item) => &mut item.attrs,
+        Item::Type(item) => &mut item.attrs,
+        Item::Union(item) => &mut item.attrs,
+        Item::Use(item) => &mut item.attrs,
+        _ => return true,
+      };
+      if !self.process_attrs(attrs) {
+        return false;
+      }
+      match item {
+        Item::Struct(item_struct) => self.strip_fields(&mut item_struct.fields),
+        Item::Union(item_union) => {
+          item_union.fields.named = std::mem::take(&mut item_union.fields.named)
+            .into_iter()
+            .filter_map(|mut field| self.process_attrs(&mut field.attrs).then_some(field))
+            .collect();
+        }
+        Item::Enum(item_enum) => {
+          item_enum.variants = std::mem::take(&mut item_enum.variants)
+            .into_iter()
+            .filter_map(|mut variant| {
+              if !self.process_attrs(&mut variant.attrs) {
+                return None;
+              }
+              self.strip_fields(&mut variant.fields);
+              Some(variant)
+            })
+            .collect();
+        }
+        Item::Impl(item_impl) => item_impl.items.retain_mut(|impl_item| {
+          let attrs = match impl_item {
+            ImplItem::Const(item) => &mut item.attrs,
+            ImplItem::Fn(item) => &mut item.attrs,
+            ImplItem::Type(item) => &mut item.attrs,
+            ImplItem::Macro(item) => &mut item.attrs,
+            _ => return true,
+          };
+          self.process_attrs(attrs)
+        }),
+        Item::Trait(item_trait) => item_trait.items.retain_mut(|trait_item| {
+          let attrs = match trait_item {
+            TraitItem::Const(item) => &mut item.attrs,
+            TraitItem::Fn(item) => &mut item.attrs,
+            TraitItem::Type(item) => &mut item.attrs,
+            TraitItem::Macro(item) => &mut item.attrs,
+            _ => return true,
+          };
+          self.process_attrs(attrs)
+        }),
+        Item::Mod(item_mod) => {
+          if let Some((_, items)) = &mut item_mod.content {
+            self.strip_items(items);
+          }
+        }
+        _ => (),
+      }
+      true
+    });
+  }
+
+  fn strip_fields(&self, fields: &mut Fields) {
+    let punctuated = match fields {
+      Fields::Named(fields) => &mut fields.named,
+      Fields::Unnamed(fields) => &mut fields.unnamed,
+      Fields::Unit => return,
+    };
+    *punctuated = std::mem::take(punctuated)
+      .into_iter()
+      .filter_map(|mut field| self.process_attrs(&mut field.attrs).then_some(field))
+      .collect();
+  }
+}
+
+impl Default for CfgSet {
+  fn default() -> Self {
+    CfgSet::host()
+  }
+}
+
+#[test]
+fn check_cfg_set() {
+  let mut cfg = CfgSet {
+    options: Vec::new(),
+  };
+  cfg.insert("target_os", Some("linux"));
+  cfg.enable_feature("std");
+  let mut file: syn::File = syn::parse_quote! {
+    #[cfg(test)]
+    pub mod tests;
+    #[cfg(all(feature = "std", not(feature = "alloc")))]
+    pub fn std_only() {}
+    #[cfg(any(target_os = "windows", doc))]
+    pub fn windows() {}
+    #[cfg_attr(feature = "std", cfg(not(target_os = "linux")))]
+    pub fn not_linux() {}
+    #[cfg_attr(feature = "std", derive(Clone))]
+    pub struct Foo {
+      pub a: u8,
+      #[cfg(feature = "serde")]
+      pub b: u8,
+    }
+  };
+  cfg.strip_items(&mut file.items);
+  let expected: syn::File = syn::parse_quote! {
+    #[cfg(all(feature = "std", not(feature = "alloc")))]
+    pub fn std_only() {}
+    #[derive(Clone)]
+    pub struct Foo {
+      pub a: u8
+    }
+  };
+  assert_eq!(expected, file);
+}
+
+#[test]
+fn check_host_cfg_set() {
+  let item: syn::ItemFn = syn::parse_quote! {
+    #[cfg(any(target_endian = "little", target_endian = "big"))]
+    #[cfg(target_has_atomic = "8")]
+    #[cfg(debug_assertions)]
+    #[cfg(panic = "unwind")]
+    #[cfg(not(test))]
+    pub fn f() {}
+  };
+  assert!(CfgSet::host().is_enabled(&item.attrs));
+  let host = CfgSet::host();
+  for name in ["target_env", "target_vendor"] {
+    assert!(host.options.iter().any(|(n, _)| n == name));
+  }
+}
+
+#[test]
+fn check_cfg_override() {
+  let enabled = |cfg: &CfgSet, item: syn::ItemFn| cfg.is_enabled(&item.attrs);
+  let mut cfg = CfgSet::host();
+  cfg.insert_spec("target_os=\"windows\"");
+  cfg.insert_spec("target_pointer_width = \"32\"");
+  assert!(enabled(
+    &cfg,
+    syn::parse_quote! {
+      #[cfg(all(target_os = "windows", windows, target_family = "windows"))]
+      #[cfg(target_pointer_width = "32")]
+      fn f() {}
+    }
+  ));
+  for item in [
+    syn::parse_quote! {
+      #[cfg(any(target_os = "linux", target_os = "macos"))]
+      fn f() {}
+    },
+    syn::parse_quote! {
+      #[cfg(unix)]
+      fn f() {}
+    },
+    syn::parse_quote! {
+      #[cfg(target_pointer_width = "64")]
+      fn f() {}
+    },
+  ] {
+    assert!(!enabled(&cfg, item));
+  }
+  cfg.insert_spec("target_os=\"linux\"");
+  assert!(enabled(
+    &cfg,
+    syn::parse_quote! {
+      #[cfg(all(unix, not(windows)))]
+      fn f() {}
+    }
+  ));
+}
@@ -0,0 +1,485 @@
+use crate::file::{self, ModuleInfo};
+use syn::{Item, Visibility};
+
+/// 公開されたパスから到達できるアイテム
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PublicItem {
+  /// 利用者から見えるパス
+  pub path: Vec<String>,
+  /// 定義されているモジュールのパス
+  pub def_mod_path: Vec<String>,
+  /// 定義されている名前
+  pub def_name: String,
+}
+
+/// 公開されたパスから再エクスポートされている、クレートの外のもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExternalReexport {
+  /// 利用者から見えるパス
+  pub path: Vec<String>,
+  /// 再エクスポートしているもののパス
+  pub target: Vec<String>,
+}
+
+/// クレートの公開APIとして到達できる範囲
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PublicSurface {
+  /// 到達できるモジュールのパス
+  pub modules: Vec<Vec<String>>,
+  pub items: Vec<PublicItem>,
+  /// 外部クレートのアイテムやモジュールの再エクスポート
+  ///
+  /// 中身は比較できないので、どのパスで何を再エクスポートしているかだけを持つ
+  pub external: Vec<ExternalReexport>,
+  /// 複数のglobが別々のものを導入していて、使えなくなっているパス
+  pub ambiguous: Vec<Vec<String>>,
+  /// 利用者がglobでimportすることが想定されるモジュールのパス
+  ///
+  /// `prelude`という名前のモジュールと、クレートの中でglobとして再エクスポートされているモジュール
+  pub glob_modules: Vec<Vec<String>>,
+}
+
+impl PublicSurface {
+  /// パスが到達できるアイテムを指していれば、その定義の絶対パスを返す
+  ///
+  /// 公開されたパスでも、定義されている場所のパスでもよい
+  pub(crate) fn def_of(&self, path: &[String]) -> Option<Vec<String>> {
+    self.items.iter().find_map(|item| {
+      let mut def = item.def_mod_path.clone();
+      def.push(item.def_name.clone());
+      (item.path == path || def == path).then_some(def)
+    })
+  }
+
+  /// 定義の絶対パスから、利用者が使う公開されたパスのうち最も短いものを求める
+  pub(crate) fn public_path_of(&self, def: &[String]) -> Option<&Vec<String>> {
+    let (name, mod_path) = def.split_last()?;
+    self
+      .items
+      .iter()
+      .filter(|item| item.def_mod_path == mod_path && item.def_name == *name)
+      .map(|item| &item.path)
+      .min_by_key(|path| path.len())
+  }
+}
+
+/// `pub use`で指されているもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExportTarget {
+  Module(Vec<String>),
+  /// 定義されているモジュールのパスと名前
+  Item(Vec<String>, String),
+  /// クレートの外のもののパス
+  External(Vec<String>),
+}
+
+pub(crate) fn mod_path_strings(module_info: &ModuleInfo) -> Vec<String> {
+  module_info.mod_path.iter().map(|i| i.to_string()).collect()
+}
+
+pub(crate) fn find_module<'a>(
+  module_info_list: &'a [ModuleInfo],
+  mod_path: &[String],
+) -> Option<&'a ModuleInfo> {
+  module_info_list.iter().find(|m| {
+    m.mod_path
+      .iter()
+      .map(|i| i.to_string())
+      .eq(mod_path.iter().cloned())
+  })
+}
+
+/// モジュールの中で定義されている公開アイテムの名前
+fn defined_names(items: &[Item]) -> Vec<String> {
+  let mut v = Vec::new();
+  for item in items.iter() {
+    match item {
+      Item::Mod(_) => (),
+      Item::Macro(item_macro) => {
+        if let Some(ident) = &item_macro.ident {
+          v.push(ident.to_string());
+        }
+      }
+      _ => {
+        if let Some((ident, Visibility::Public(_))) = file::item_ident_vis(item) {
+          v.push(ident.to_string());
+        }
+      }
+    }
+  }
+  v
+}
+
+/// 名前空間（型、値、マクロ）のどれに属するか
+fn target_namespaces(module_info_list: &[ModuleInfo], target: &ExportTarget) -> [bool; 3] {
+  let ExportTarget::Item(mod_path, name) = target else {
+    return [true, false, false];
+  };
+  let mut namespaces = [false; 3];
+  let Some(module_info) = find_module(module_info_list, mod_path) else {
+    return namespaces;
+  };
+  for item in module_info.items.iter() {
+    match item {
+      Item::Macro(item_macro) => {
+        if item_macro.ident.as_ref().is_some_and(|i| i == name) {
+          namespaces[2] = true;
+        }
+      }
+      Item::Struct(item_struct) if item_struct.ident == name => {
+        namespaces[0] = true;
+        // タプル構造体とユニット構造体はコンストラクタが値の名前空間に入る
+        if !matches!(item_struct.fields, syn::Fields::Named(_)) {
+          namespaces[1] = true;
+        }
+      }
+      Item::Fn(_) | Item::Const(_) | Item::Static(_) => {
+        if file::item_ident_vis(item).is_some_and(|(i, _)| i == name) {
+          namespaces[1] = true;
+        }
+      }
+      _ => {
+        if file::item_ident_vis(item).is_some_and(|(i, _)| i == name) {
+          namespaces[0] = true;
+        }
+      }
+    }
+  }
+  namespaces
+}
+
+/// 2つのものが同じ名前空間で衝突するか
+fn same_namespace(module_info_list: &[ModuleInfo], a: &ExportTarget, b: &ExportTarget) -> bool {
+  let a = target_namespaces(module_info_list, a);
+  let b = target_namespaces(module_info_list, b);
+  (0..3).any(|i| a[i] && b[i])
+}
+
+/// モジュールから`モジュール::名前`として公開されている名前と、それが指すもの
+///
+/// globで導入された名前は、明示的に導入された名前に隠される。
+/// 複数のglobが別々のものを同じ名前で導入していると曖昧で使えないので、2つ目の返り値に入れる
+fn module_exports(
+  module_info_list: &[ModuleInfo],
+  module_info: &ModuleInfo,
+  visiting: &mut Vec<Vec<String>>,
+) -> (Vec<(String, ExportTarget)>, Vec<String>) {
+  let mod_path = mod_path_strings(module_info);
+  // `pub use`やglobが循環していても止まるようにする
+  if visiting.contains(&mod_path) {
+    return (Vec::new(), Vec::new());
+  }
+  visiting.push(mod_path.clone());
+
+  let mut exports = Vec::new();
+  for name in defined_names(&module_info.items) {
+    exports.push((name.clone(), ExportTarget::Item(mod_path.clone(), name)));
+  }
+  for item_mod in file::get_children_file_name(&module_info.items) {
+    // 非公開のモジュールは`pub use`で再エクスポートされた場合だけ到達できる
+    if !matches!(item_mod.vis, Visibility::Public(_)) {
+      continue;
+    }
+    let mut child_path = mod_path.clone();
+    child_path.push(item_mod.ident.to_string());
+    if find_module(module_info_list, &child_path).is_some() {
+      exports.push((item_mod.ident.to_string(), ExportTarget::Module(child_path)));
+    }
+  }
+  for (name, import) in file::pub_use_imports(&module_info.items) {
+    let Some(local_path) = file::resolve_local_path(module_info, &import) else {
+      let target = ExportTarget::External(file::canonical_path(module_info, &import));
+      exports.push((name, target));
+      continue;
+    };
+    for target in resolve_export(module_info_list, &local_path, visiting) {
+      exports.push((name.clone(), target));
+    }
+  }
+
+  let mut glob_exports: Vec<(String, ExportTarget)> = Vec::new();
+  let mut ambiguous = Vec::new();
+  for glob in file::pub_glob_imports(&module_info.items) {
+    let Some(local_path) = file::resolve_local_path(module_info, &glob) else {
+      continue;
+    };
+    let Some(glob_module) = find_module(module_info_list, &local_path) else {
+      continue;
+    };
+    let (glob_module_exports, _) = module_exports(module_info_list, glob_module, visiting);
+    for (name, target) in glob_module_exports {
+      let shadowed = exports
+        .iter()
+        .any(|(n, t)| *n == name && same_namespace(module_info_list, t, &target));
+      if shadowed || glob_exports.contains(&(name.clone(), target.clone())) {
+        continue;
+      }
+      if glob_exports
+        .iter()
+        .any(|(n, t)| *n == name && same_namespace(module_info_list, t, &target))
+      {
+        if !ambiguous.contains(&name) {
+          ambiguous.push(name);
+        }
+        continue;
+      }
+      glob_exports.push((name, target));
+    }
+  }
+  glob_exports.retain(|(n, _)| !ambiguous.contains(n));
+  exports.extend(glob_exports);
+
+  visiting.pop();
+  (exports, ambiguous)
+}
+
+/// クレート内の絶対パスが指すものを、`pub use`をたどって求める
+///
+/// 名前空間が違えば同じ名前のモジュールとアイテムが両方あり得るので、全て返す
+fn resolve_export(
+  module_info_list: &[ModuleInfo],
+  path: &[String],
+  visiting: &mut Vec<Vec<String>>,
+) -> Vec<ExportTarget> {
+  let mut v = Vec::new();
+  if find_module(module_info_list, path).is_some() {
+    v.push(ExportTarget::Module(path.to_vec()));
+  }
+  let Some((name, mod_path)) = path.split_last() else {
+    return v;
+  };
+  let Some(module_info) = find_module(module_info_list, mod_path) else {
+    return v;
+  };
+  let (exports, _) = module_exports(module_info_list, module_info, visiting);
+  for (_, target) in exports.into_iter().filter(|(n, _)| n == name) {
+    if !v.contains(&target) {
+      v.push(target);
+    }
+  }
+  v
+}
+
+fn walk(
+  module_info_list: &[ModuleInfo],
+  module_info: &ModuleInfo,
+  prefix: Vec<String>,
+  glob_targets: &[Vec<String>],
+  surface: &mut PublicSurface,
+) {
+  // モジュールを自分自身の中に再エクスポートしていても止まるようにする
+  if prefix.len() > 16 || surface.modules.contains(&prefix) {
+    return;
+  }
+  surface.modules.push(prefix.clone());
+  let mod_path = mod_path_strings(module_info);
+  if glob_targets.contains(&mod_path) || mod_path.last().is_some_and(|n| n == "prelude") {
+    surface.glob_modules.push(prefix.clone());
+  }
+
+  let (exports, ambiguous) = module_exports(module_info_list, module_info, &mut Vec::new());
+  for name in ambiguous {
+    let mut path = prefix.clone();
+    path.push(name);
+    surface.ambiguous.push(path);
+  }
+  for (name, target) in exports {
+    let mut path = prefix.clone();
+    path.push(name);
+    match target {
+      ExportTarget::Module(target_mod_path) => {
+        if let Some(target) = find_module(module_info_list, &target_mod_path) {
+          walk(module_info_list, target, path, glob_targets, surface);
+        }
+      }
+      ExportTarget::Item(def_mod_path, def_name) => {
+        let item = PublicItem {
+          path,
+          def_mod_path,
+          def_name,
+        };
+        if !surface.items.contains(&item) {
+          surface.items.push(item);
+        }
+      }
+      ExportTarget::External(target) => {
+        let reexport = ExternalReexport { path, target };
+        if !surface.external.contains(&reexport) {
+          surface.external.push(reexport);
+        }
+      }
+    }
+  }
+}
+
+/// クレートのルートから、公開された`mod`と`pub use`をたどって到達できるパスを集める
+pub(crate) fn public_surface(module_info_list: &[ModuleInfo]) -> PublicSurface {
+  // globで再エクスポートされているモジュール
+  let mut glob_targets = Vec::new();
+  for module_info in module_info_list.iter() {
+    for glob in file::pub_glob_imports(&module_info.items) {
+      if let Some(local_path) = file::resolve_local_path(module_info, &glob) {
+        glob_targets.push(local_path);
+      }
+    }
+  }
+  let mut surface = PublicSurface::default();
+  if let Some(root) = find_module(module_info_list, &[]) {
+    walk(
+      module_info_list,
+      root,
+      Vec::new(),
+      &glob_targets,
+      &mut surface,
+    );
+  }
+  surface
+}
+
+#[test]
+fn check_public_surface() {
+  let root: syn::File = syn::parse_quote! {
+    pub mod inner;
+    pub use inner::Foo as Bar;
+    pub use self::inner::helpers;
+    pub use crate::inner::helpers::help;
+    pub use std::collections::HashMap as Map;
+  };
+  let inner: syn::File = syn::parse_quote! {
+    pub struct Foo;
+    pub mod helpers;
+  };
+  let helpers: syn::File = syn::parse_quote! {
+    pub fn help() {}
+  };
+  let module_info_list = vec![
+    ModuleInfo::new(vec![syn::parse_quote!(inner)], inner.items),
+    ModuleInfo::new(
+      vec![syn::parse_quote!(inner), syn::parse_quote!(helpers)],
+      helpers.items,
+    ),
+    ModuleInfo::new(Vec::new(), root.items),
+  ];
+  let surface = public_surface(&module_info_list);
+  let paths = surface
+    .items
+    .iter()
+    .map(|i| i.path.join("::"))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      "inner::Foo",
+      "inner::helpers::help",
+      "Bar",
+      "helpers::help",
+      "help"
+    ],
+    paths
+  );
+  assert_eq!(vec!["inner".to_string()], surface.items[2].def_mod_path);
+  assert_eq!(
+    vec![ExternalReexport {
+      path: vec!["Map".to_string()],
+      target: vec![
+        "std".to_string(),
+        "collections".to_string(),
+        "HashMap".to_string()
+      ],
+    }],
+    surface.external
+  );
+}
+
+#[test]
+fn check_glob_exports() {
+  let root: syn::File = syn::parse_quote! {
+    pub mod a;
+    pub mod b;
+    pub mod prelude;
+  };
+  let prelude: syn::File = syn::parse_quote! {
+    pub use crate::a::*;
+    pub use crate::b::*;
+    pub fn only_here() {}
+  };
+  let a: syn::File = syn::parse_quote! {
+    pub struct Both;
+    pub struct OnlyA {
+      pub x: u8,
+    }
+    pub fn only_here() {}
+  };
+  let b: syn::File = syn::parse_quote! {
+    pub struct Both;
+    pub fn OnlyA() {}
+  };
+  let module_info_list = vec![
+    ModuleInfo::new(vec![syn::parse_quote!(a)], a.items),
+    ModuleInfo::new(vec![syn::parse_quote!(b)], b.items),
+    ModuleInfo::new(vec![syn::parse_quote!(prelude)], prelude.items),
+    ModuleInfo::new(Vec::new(), root.items),
+  ];
+  let surface = public_surface(&module_info_list);
+  let prelude_items = surface
+    .items
+    .iter()
+    .filter(|i| i.path[0] == "prelude")
+    .map(|i| (i.path[1].as_str(), i.def_mod_path.join("::")))
+    .collect::<Vec<_>>();
+  // 明示的に定義された`only_here`がglobより優先され、
+  // `OnlyA`は構造体と関数で名前空間が違うので両方使える
+  assert_eq!(
+    vec![
+      ("only_here", "prelude".to_string()),
+      ("OnlyA", "a".to_string()),
+      ("OnlyA", "b".to_string())
+    ],
+    prelude_items
+  );
+  assert_eq!(
+    vec![vec!["prelude".to_string(), "Both".to_string()]],
+    surface.ambiguous
+  );
+  assert!(surface.glob_modules.contains(&vec!["a".to_string()]));
+  assert!(surface.glob_modules.contains(&vec!["prelude".to_string()]));
+}
+
+#[test]
+fn check_private_module_reachability() {
+  let root: syn::File = syn::parse_quote! {
+    mod private;
+    pub use private::Reexported;
+  };
+  let private: syn::File = syn::parse_quote! {
+    pub struct Reexported;
+    pub struct Hidden;
+    pub mod nested;
+  };
+  let nested: syn::File = syn::parse_quote! {
+    pub struct AlsoHidden;
+  };
+  let module_info_list = vec![
+    ModuleInfo::new(vec![syn::parse_quote!(private)], private.items),
+    ModuleInfo::new(
+      vec![syn::parse_quote!(private), syn::parse_quote!(nested)],
+      nested.items,
+    ),
+    ModuleInfo::new(Vec::new(), root.items),
+  ];
+  let surface = public_surface(&module_info_list);
+  let to_path = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+  assert_eq!(vec![to_path(&[])], surface.modules);
+  assert_eq!(
+    Some(to_path(&["private", "Reexported"])),
+    surface.def_of(&to_path(&["Reexported"]))
+  );
+  assert_eq!(
+    Some(&to_path(&["Reexported"])),
+    surface.public_path_of(&to_path(&["private", "Reexported"]))
+  );
+  assert_eq!(None, surface.def_of(&to_path(&["private", "Hidden"])));
+  assert_eq!(
+    None,
+    surface.def_of(&to_path(&["private", "nested", "AlsoHidden"]))
+  );
+}
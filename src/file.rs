@@ -1,51 +1,112 @@
+use crate::attrs;
+use crate::cfg::CfgSet;
+use crate::source::ModuleSource;
 use anyhow::anyhow;
-use std::path::{Path, PathBuf};
-use syn::token::Brace;
-use syn::{Ident, Item, Visibility};
+use std::path::{Component, Path, PathBuf};
+use syn::{Ident, Item, ItemMod, UseTree, Visibility};
 
-#[allow(clippy::type_complexity)]
 /// 子モジュールの名前を見つけに行く
-pub(crate) fn get_children_file_name(items: &[Item]) -> Vec<(Ident, Option<(Brace, Vec<Item>)>)> {
-  let mut mod_name_list = Vec::new();
+///
+/// 非公開のモジュールも`pub use`で再エクスポートされうるので全て含める
+pub(crate) fn get_children_file_name(items: &[Item]) -> Vec<&ItemMod> {
+  let mut mod_list = Vec::new();
   for item in items.iter() {
     if let Item::Mod(m) = item {
-      // pubのみ扱う
-      // `pub(crate)`などは後方互換性に影響を与えないので無視する
-      if let Visibility::Public(_) = m.vis {
-        mod_name_list.push((m.ident.clone(), m.content.clone()))
-      }
+      mod_list.push(m)
     }
   }
-  mod_name_list
+  mod_list
 }
 
-/// 子モジュールのファイルを見に行く
-pub(crate) fn check_mod_file_exists(
-  pwd: &Path,
-  mod_name_list: &[Ident],
-) -> anyhow::Result<PathBuf> {
-  let mut file_path = pwd.to_path_buf();
-  for name in mod_name_list.iter() {
-    file_path = file_path.join(name.to_string())
-  }
-  // ed2015でのファイルのパス
-  let ed2015_file_path = file_path.join("mod.rs");
-  // ed2018でのファイルのパス
-  file_path.set_extension("rs");
+/// 子モジュールのファイルを探すディレクトリ
+///
+/// どちらもソースのルート（`lib.rs`のあるディレクトリ）からの相対パス
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModDirs {
+  /// 名前から子モジュールのファイルを探すディレクトリ
+  pub children: PathBuf,
+  /// `#[path]`の相対パスの基準になるディレクトリ
+  pub path_attr: PathBuf,
+}
 
-  if file_path.try_exists()? {
-    return Ok(file_path);
+/// `.`と`..`を取り除いたパスにする
+///
+/// gitのツリーは`..`を含むパスを辿れない
+fn normalize_path(path: &Path) -> PathBuf {
+  let mut v = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => (),
+      Component::ParentDir => {
+        v.pop();
+      }
+      c => v.push(c),
+    }
   }
+  v
+}
 
-  if ed2015_file_path.try_exists()? {
-    return Ok(ed2015_file_path);
+/// 子モジュールのファイルを読み込み、その中身と孫モジュールを探すディレクトリを返す
+///
+/// ファイルが見つからなければ`None`を返す
+fn load_mod_file<S>(
+  item_mod: &ItemMod,
+  dirs: &ModDirs,
+  source: &S,
+) -> anyhow::Result<Option<(String, ModDirs)>>
+where
+  S: ModuleSource + ?Sized,
+{
+  if let Some(path) = attrs::path_attr(&item_mod.attrs) {
+    let file_path = normalize_path(&dirs.path_attr.join(path));
+    let Some(contents) = source.read_file(&file_path)? else {
+      return Ok(None);
+    };
+    // `#[path]`で読み込んだファイルは`mod.rs`と同じ扱いになる
+    let dir = file_path
+      .parent()
+      .map(Path::to_path_buf)
+      .unwrap_or_default();
+    return Ok(Some((
+      contents,
+      ModDirs {
+        children: dir.clone(),
+        path_attr: dir,
+      },
+    )));
+  }
+  let name = item_mod.ident.to_string();
+  let file_path = dirs.children.join(format!("{name}.rs"));
+  let mod_rs_path = dirs.children.join(&name).join("mod.rs");
+  match (
+    source.read_file(&file_path)?,
+    source.read_file(&mod_rs_path)?,
+  ) {
+    (Some(_), Some(_)) => Err(anyhow!(
+      "file for module `{name}` found at both {} and {}",
+      file_path.display(),
+      mod_rs_path.display()
+    )),
+    // `foo.rs`の子モジュールは`foo/`に置くが、`#[path]`は`foo.rs`のあるディレクトリが基準になる
+    (Some(contents), None) => Ok(Some((
+      contents,
+      ModDirs {
+        children: dirs.children.join(&name),
+        path_attr: dirs.children.clone(),
+      },
+    ))),
+    (None, Some(contents)) => {
+      let dir = dirs.children.join(&name);
+      Ok(Some((
+        contents,
+        ModDirs {
+          children: dir.clone(),
+          path_attr: dir,
+        },
+      )))
+    }
+    (None, None) => Ok(None),
   }
-
-  Err(anyhow!(
-    "not found child file: {} or {}",
-    ed2015_file_path.display(),
-    file_path.display()
-  ))
 }
 
 /// ファイルの情報
@@ -55,42 +116,665 @@ pub(crate) struct ModuleInfo {
   pub mod_path: Vec<Ident>,
   /// moduleの中身
   pub items: Vec<Item>,
+  /// `use`で導入された名前と、そのパス
+  ///
+  /// [`expand_glob_imports`]の後は、クレート内のモジュールのglobで導入された名前も含む
+  pub imports: Vec<(String, Vec<String>)>,
+  /// `use ...::*`でglobとしてimportされたパス
+  pub globs: Vec<Vec<String>>,
+}
+
+impl ModuleInfo {
+  pub(crate) fn new(mod_path: Vec<Ident>, items: Vec<Item>) -> Self {
+    let imports = use_imports(&items);
+    let globs = glob_imports(&items);
+    ModuleInfo {
+      mod_path,
+      items,
+      imports,
+      globs,
+    }
+  }
+}
+
+/// クレートのルートのファイルから、全てのモジュールの情報を取得する
+///
+/// `cfg`で除外されるアイテムは取り除く。ルートのモジュールは最後に入る。
+/// ルートのファイルが無ければ`None`を返す
+pub(crate) fn load_crate_modules<S>(
+  source: &S,
+  root_file: &Path,
+  cfg: &CfgSet,
+) -> anyhow::Result<Option<Vec<ModuleInfo>>>
+where
+  S: ModuleSource + ?Sized,
+{
+  let Some(contents) = source.read_file(root_file)? else {
+    return Ok(None);
+  };
+  let mut file = syn::parse_file(&contents)?;
+  cfg.strip_items(&mut file.items);
+  let root = ModuleInfo::new(Vec::new(), file.items);
+  let mut v = get_children_modules(&root, &ModDirs::default(), source, cfg)?;
+  v.push(root);
+  expand_glob_imports(&mut v);
+  Ok(Some(v))
 }
 
 /// 階層が下のモジュールの情報を再帰的に取得する
-pub(crate) fn get_children_modules<F>(
+///
+/// `module_info`のアイテムは`cfg`で除外されるものを取り除いてある必要がある
+pub(crate) fn get_children_modules<S>(
   module_info: &ModuleInfo,
-  get_children_files: &F,
+  dirs: &ModDirs,
+  source: &S,
+  cfg: &CfgSet,
 ) -> anyhow::Result<Vec<ModuleInfo>>
 where
-  F: Fn(&[Ident]) -> anyhow::Result<String>,
+  S: ModuleSource + ?Sized,
 {
   let mut v = Vec::new();
 
   // ファイルに含まれる`mod`から子階層を呼び出す
-  let file_name_list = get_children_file_name(&module_info.items);
-  for (name, mod_contents_opt) in file_name_list.iter() {
+  for item_mod in get_children_file_name(&module_info.items) {
     let mut p = module_info.mod_path.clone();
-    p.push(name.clone());
-    if let Some((_, contents)) = mod_contents_opt {
-      let info = ModuleInfo {
-        mod_path: p,
-        items: contents.clone(),
+    p.push(item_mod.ident.clone());
+    if let Some((_, contents)) = &item_mod.content {
+      // インラインのモジュールの中では、子モジュールも`#[path]`もそのモジュールのディレクトリが基準になる
+      let dir = match attrs::path_attr(&item_mod.attrs) {
+        Some(path) => normalize_path(&dirs.children.join(path)),
+        None => dirs.children.join(item_mod.ident.to_string()),
       };
-      let children = get_children_modules(&info, get_children_files)?;
+      let child_dirs = ModDirs {
+        children: dir.clone(),
+        path_attr: dir,
+      };
+      let info = ModuleInfo::new(p, contents.clone());
+      let children = get_children_modules(&info, &child_dirs, source, cfg)?;
       v.push(info);
       v.extend(children);
     } else {
-      let contents = get_children_files(&p)?;
-      let file = syn::parse_file(&contents)?;
-      let info = ModuleInfo {
-        mod_path: p,
-        items: file.items,
+      let Some((contents, child_dirs)) = load_mod_file(item_mod, dirs, source)? else {
+        // 非公開のモジュールは見つからなくても公開APIの比較は続けられる
+        if !matches!(item_mod.vis, Visibility::Public(_)) {
+          continue;
+        }
+        return Err(anyhow!(
+          "not found child file for module `{}` in {}",
+          item_mod.ident,
+          dirs.children.display()
+        ));
       };
-      let children = get_children_modules(&info, get_children_files)?;
+      let mut file = syn::parse_file(&contents)?;
+      // ファイルの先頭の`#![cfg(...)]`はモジュール全体にかかる
+      if !cfg.is_enabled(&file.attrs) {
+        continue;
+      }
+      cfg.strip_items(&mut file.items);
+      let info = ModuleInfo::new(p, file.items);
+      let children = get_children_modules(&info, &child_dirs, source, cfg)?;
       v.push(info);
       v.extend(children);
     }
   }
   Ok(v)
 }
+
+/// テスト用のメモリ上のファイル
+#[cfg(test)]
+struct MemorySource<'a>(&'a [(&'a str, &'a str)]);
+
+#[cfg(test)]
+impl ModuleSource for MemorySource<'_> {
+  fn read_file(&self, path: &Path) -> anyhow::Result<Option<String>> {
+    Ok(
+      self
+        .0
+        .iter()
+        .find(|(name, _)| Path::new(name) == path)
+        .map(|(_, contents)| contents.to_string()),
+    )
+  }
+}
+
+#[test]
+fn check_get_children_modules_path_attr() {
+  let files = [
+    ("platform/unix.rs", "pub mod detail;"),
+    ("platform/detail.rs", ""),
+    (
+      "a.rs",
+      "#[path = \"other.rs\"] pub mod b; pub mod inline { pub mod c; } pub mod d;",
+    ),
+    ("a/d.rs", ""),
+    ("other.rs", ""),
+    ("a/inline/c.rs", ""),
+    (
+      "nested/mod.rs",
+      "pub mod x { #[path = \"y.rs\"] pub mod y; }",
+    ),
+    ("nested/x/y.rs", ""),
+  ];
+  let root: syn::File = syn::parse_quote! {
+    #[path = "platform/unix.rs"]
+    pub mod sys;
+    pub mod a;
+    pub mod nested;
+  };
+  let modules = get_children_modules(
+    &ModuleInfo::new(Vec::new(), root.items),
+    &ModDirs::default(),
+    &MemorySource(&files),
+    &CfgSet::default(),
+  )
+  .unwrap();
+  let paths = modules
+    .iter()
+    .map(|m| {
+      m.mod_path
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      "sys",
+      "sys::detail",
+      "a",
+      "a::b",
+      "a::inline",
+      "a::inline::c",
+      "a::d",
+      "nested",
+      "nested::x",
+      "nested::x::y"
+    ],
+    paths
+  );
+}
+
+/// アイテムの名前と可視性を取得する
+pub(crate) fn item_ident_vis(item: &Item) -> Option<(&Ident, &Visibility)> {
+  match item {
+    Item::Const(i) => Some((&i.ident, &i.vis)),
+    Item::Enum(i) => Some((&i.ident, &i.vis)),
+    Item::Fn(i) => Some((&i.sig.ident, &i.vis)),
+    Item::Mod(i) => Some((&i.ident, &i.vis)),
+    Item::Static(i) => Some((&i.ident, &i.vis)),
+    Item::Struct(i) => Some((&i.ident, &i.vis)),
+    Item::Trait(i) => Some((&i.ident, &i.vis)),
+    Item::TraitAlias(i) => Some((&i.ident, &i.vis)),
+    Item::Type(i) => Some((&i.ident, &i.vis)),
+    Item::Union(i) => Some((&i.ident, &i.vis)),
+    _ => None,
+  }
+}
+
+/// `use`の木から、導入される名前とそのパスを集める
+///
+/// globは名前を特定できないので含めない
+fn collect_use_tree(tree: &UseTree, prefix: &mut Vec<String>, v: &mut Vec<(String, Vec<String>)>) {
+  match tree {
+    UseTree::Path(p) => {
+      prefix.push(p.ident.to_string());
+      collect_use_tree(&p.tree, prefix, v);
+      prefix.pop();
+    }
+    UseTree::Name(n) => {
+      let name = n.ident.to_string();
+      if name == "self" {
+        if let Some(last) = prefix.last() {
+          v.push((last.clone(), prefix.clone()));
+        }
+      } else {
+        let mut path = prefix.clone();
+        path.push(name.clone());
+        v.push((name, path));
+      }
+    }
+    UseTree::Rename(r) => {
+      let mut path = prefix.clone();
+      if r.ident != "self" {
+        path.push(r.ident.to_string());
+      }
+      v.push((r.rename.to_string(), path));
+    }
+    UseTree::Glob(_) => (),
+    UseTree::Group(g) => {
+      for tree in g.items.iter() {
+        collect_use_tree(tree, prefix, v);
+      }
+    }
+  }
+}
+
+/// `use`で導入された名前と、そのパスを集める
+pub(crate) fn use_imports(items: &[Item]) -> Vec<(String, Vec<String>)> {
+  let mut v = Vec::new();
+  for item in items.iter() {
+    if let Item::Use(item_use) = item {
+      collect_use_tree(&item_use.tree, &mut Vec::new(), &mut v);
+    }
+  }
+  v
+}
+
+/// `pub use`で再エクスポートされた名前と、そのパスを集める
+pub(crate) fn pub_use_imports(items: &[Item]) -> Vec<(String, Vec<String>)> {
+  let mut v = Vec::new();
+  for item in items.iter() {
+    if let Item::Use(item_use) = item {
+      if let Visibility::Public(_) = item_use.vis {
+        collect_use_tree(&item_use.tree, &mut Vec::new(), &mut v);
+      }
+    }
+  }
+  v
+}
+
+/// `use`の木から、globでimportされるパスを集める
+fn collect_glob_tree(tree: &UseTree, prefix: &mut Vec<String>, v: &mut Vec<Vec<String>>) {
+  match tree {
+    UseTree::Path(p) => {
+      prefix.push(p.ident.to_string());
+      collect_glob_tree(&p.tree, prefix, v);
+      prefix.pop();
+    }
+    UseTree::Glob(_) => v.push(prefix.clone()),
+    UseTree::Group(g) => {
+      for tree in g.items.iter() {
+        collect_glob_tree(tree, prefix, v);
+      }
+    }
+    UseTree::Name(_) | UseTree::Rename(_) => (),
+  }
+}
+
+/// `use ...::*`でglobとしてimportされたパスを集める
+pub(crate) fn glob_imports(items: &[Item]) -> Vec<Vec<String>> {
+  let mut v = Vec::new();
+  for item in items.iter() {
+    if let Item::Use(item_use) = item {
+      collect_glob_tree(&item_use.tree, &mut Vec::new(), &mut v);
+    }
+  }
+  v
+}
+
+/// `pub use ...::*`でglobとして再エクスポートされたパスを集める
+pub(crate) fn pub_glob_imports(items: &[Item]) -> Vec<Vec<String>> {
+  let mut v = Vec::new();
+  for item in items.iter() {
+    if let Item::Use(item_use) = item {
+      if let Visibility::Public(_) = item_use.vis {
+        collect_glob_tree(&item_use.tree, &mut Vec::new(), &mut v);
+      }
+    }
+  }
+  v
+}
+
+/// `module_info`の中に書かれた`globs`で、`importer`から使える名前と、その正規化したパスを集める
+///
+/// 非公開のアイテムは、`importer`がglobの指すモジュールの中にある場合だけ使える
+fn glob_names(
+  module_info_list: &[ModuleInfo],
+  module_info: &ModuleInfo,
+  globs: &[Vec<String>],
+  importer: &[String],
+  visiting: &mut Vec<Vec<String>>,
+) -> Vec<(String, Vec<String>)> {
+  let mut v: Vec<(String, Vec<String>)> = Vec::new();
+  for glob in globs.iter() {
+    let Some(target_path) = resolve_local_path(module_info, glob) else {
+      continue;
+    };
+    // globが循環していても止まるようにする
+    if visiting.contains(&target_path) {
+      continue;
+    }
+    let Some(target) = module_info_list.iter().find(|m| {
+      m.mod_path
+        .iter()
+        .map(|i| i.to_string())
+        .eq(target_path.iter().cloned())
+    }) else {
+      continue;
+    };
+    visiting.push(target_path.clone());
+    let private_visible = importer.starts_with(&target_path);
+    // 明示的に導入された名前が、globで導入された名前より優先される
+    let mut names = Vec::new();
+    for item in target.items.iter() {
+      let Some((ident, vis)) = item_ident_vis(item) else {
+        continue;
+      };
+      if private_visible || !matches!(vis, Visibility::Inherited) {
+        let mut path = vec!["crate".to_string()];
+        path.extend(target_path.iter().cloned());
+        path.push(ident.to_string());
+        names.push((ident.to_string(), path));
+      }
+    }
+    let (imports, target_globs) = if private_visible {
+      (target.imports.clone(), target.globs.clone())
+    } else {
+      (
+        pub_use_imports(&target.items),
+        pub_glob_imports(&target.items),
+      )
+    };
+    for (name, path) in imports {
+      names.push((name, canonical_path(target, &path)));
+    }
+    names.extend(glob_names(
+      module_info_list,
+      target,
+      &target_globs,
+      importer,
+      visiting,
+    ));
+    visiting.pop();
+    for (name, path) in names {
+      if !v.iter().any(|(n, _)| *n == name) {
+        v.push((name, path));
+      }
+    }
+  }
+  v
+}
+
+/// クレート内のモジュールのglobで導入される名前を、それぞれのモジュールの`imports`に加える
+///
+/// モジュールの中で定義された名前や、明示的に導入された名前が優先される
+pub(crate) fn expand_glob_imports(module_info_list: &mut [ModuleInfo]) {
+  let expanded = module_info_list
+    .iter()
+    .map(|module_info| {
+      let mod_path = module_info
+        .mod_path
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>();
+      glob_names(
+        module_info_list,
+        module_info,
+        &module_info.globs,
+        &mod_path,
+        &mut vec![mod_path.clone()],
+      )
+    })
+    .collect::<Vec<_>>();
+  for (module_info, names) in module_info_list.iter_mut().zip(expanded) {
+    for (name, path) in names {
+      let defined = module_info
+        .items
+        .iter()
+        .any(|item| item_ident_vis(item).is_some_and(|(ident, _)| *ident == name));
+      if !defined && !module_info.imports.iter().any(|(n, _)| *n == name) {
+        module_info.imports.push((name, path));
+      }
+    }
+  }
+}
+
+/// クレートの外を指すglobが1つだけあれば、そのパスを返す
+///
+/// どの名前が導入されるかは分からないので、モジュールの中で解決できない名前はここから来たものとみなす
+pub(crate) fn external_glob(module_info: &ModuleInfo) -> Option<Vec<String>> {
+  let mut external = module_info
+    .globs
+    .iter()
+    .filter(|glob| resolve_local_path(module_info, glob).is_none());
+  match (external.next(), external.next()) {
+    (Some(glob), None) => Some(canonical_path(module_info, glob)),
+    _ => None,
+  }
+}
+
+/// モジュールの中で書かれたパスを、クレートの中での絶対パスに解決する
+///
+/// 外部クレートやpreludeに由来するものは`None`を返す
+pub(crate) fn resolve_local_path(
+  module_info: &ModuleInfo,
+  segments: &[String],
+) -> Option<Vec<String>> {
+  resolve_local_path_inner(
+    &module_info
+      .mod_path
+      .iter()
+      .map(|i| i.to_string())
+      .collect::<Vec<_>>(),
+    &module_info.items,
+    &module_info.imports,
+    segments,
+    0,
+  )
+}
+
+fn resolve_local_path_inner(
+  mod_path: &[String],
+  items: &[Item],
+  imports: &[(String, Vec<String>)],
+  segments: &[String],
+  depth: usize,
+) -> Option<Vec<String>> {
+  // `use`が循環していても止まるようにする
+  if depth > 16 {
+    return None;
+  }
+  let first = segments.first()?;
+  let mut base = mod_path.to_vec();
+  match first.as_str() {
+    "crate" => {
+      base.clear();
+      base.extend_from_slice(&segments[1..]);
+      Some(base)
+    }
+    "self" | "super" => {
+      let mut rest = segments;
+      while let Some(s) = rest.first() {
+        match s.as_str() {
+          "self" => (),
+          "super" => {
+            base.pop()?;
+          }
+          _ => break,
+        }
+        rest = &rest[1..];
+      }
+      base.extend_from_slice(rest);
+      Some(base)
+    }
+    _ => {
+      if let Some((_, path)) = imports.iter().find(|(n, _)| n == first) {
+        let mut path = path.clone();
+        path.extend_from_slice(&segments[1..]);
+        resolve_local_path_inner(mod_path, items, imports, &path, depth + 1)
+      } else if items
+        .iter()
+        .any(|item| item_ident_vis(item).is_some_and(|(ident, _)| ident == first))
+      {
+        base.extend_from_slice(segments);
+        Some(base)
+      } else {
+        None
+      }
+    }
+  }
+}
+
+/// 標準ライブラリのpreludeで導入される名前
+const PRELUDE: [(&str, &str); 31] = [
+  ("Copy", "std::marker::Copy"),
+  ("Send", "std::marker::Send"),
+  ("Sized", "std::marker::Sized"),
+  ("Sync", "std::marker::Sync"),
+  ("Unpin", "std::marker::Unpin"),
+  ("Drop", "std::ops::Drop"),
+  ("Fn", "std::ops::Fn"),
+  ("FnMut", "std::ops::FnMut"),
+  ("FnOnce", "std::ops::FnOnce"),
+  ("Box", "std::boxed::Box"),
+  ("ToOwned", "std::borrow::ToOwned"),
+  ("Clone", "std::clone::Clone"),
+  ("PartialEq", "std::cmp::PartialEq"),
+  ("PartialOrd", "std::cmp::PartialOrd"),
+  ("Eq", "std::cmp::Eq"),
+  ("Ord", "std::cmp::Ord"),
+  ("AsRef", "std::convert::AsRef"),
+  ("AsMut", "std::convert::AsMut"),
+  ("Into", "std::convert::Into"),
+  ("From", "std::convert::From"),
+  ("TryFrom", "std::convert::TryFrom"),
+  ("TryInto", "std::convert::TryInto"),
+  ("Default", "std::default::Default"),
+  ("Iterator", "std::iter::Iterator"),
+  ("IntoIterator", "std::iter::IntoIterator"),
+  ("FromIterator", "std::iter::FromIterator"),
+  ("Option", "std::option::Option"),
+  ("Result", "std::result::Result"),
+  ("String", "std::string::String"),
+  ("ToString", "std::string::ToString"),
+  ("Vec", "std::vec::Vec"),
+];
+
+/// 標準ライブラリのpreludeで導入される名前かどうか
+pub(crate) fn is_prelude_name(name: &str) -> bool {
+  PRELUDE.iter().any(|(n, _)| *n == name)
+}
+
+/// パスを比較できる形に正規化する
+///
+/// クレート内のアイテムは`crate`から始まる絶対パスに、外部のアイテムは`use`とpreludeを展開したパスにする
+pub(crate) fn canonical_path(module_info: &ModuleInfo, segments: &[String]) -> Vec<String> {
+  if let Some(local_path) = resolve_local_path(module_info, segments) {
+    let mut path = vec!["crate".to_string()];
+    path.extend(local_path);
+    return path;
+  }
+  let Some(first) = segments.first() else {
+    return Vec::new();
+  };
+  let mut path = if let Some((_, imported)) = module_info.imports.iter().find(|(n, _)| n == first) {
+    let mut imported = imported.clone();
+    imported.extend_from_slice(&segments[1..]);
+    imported
+  } else if let (1, Some((_, prelude_path))) =
+    (segments.len(), PRELUDE.iter().find(|(n, _)| n == first))
+  {
+    prelude_path.split("::").map(|s| s.to_string()).collect()
+  } else {
+    segments.to_vec()
+  };
+  // `core`と`alloc`のアイテムは`std`から再エクスポートされている
+  if path[0] == "core" || path[0] == "alloc" {
+    path[0] = "std".to_string();
+  }
+  path
+}
+
+#[test]
+fn check_resolve_local_path() {
+  let file: syn::File = syn::parse_quote! {
+    mod private {}
+    use self::private::Sealed as S;
+  };
+  let module_info = ModuleInfo::new(vec![syn::parse_quote!(m)], file.items);
+  let to_path = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+  assert_eq!(
+    Some(to_path(&["m", "private", "Sealed"])),
+    resolve_local_path(&module_info, &to_path(&["S"]))
+  );
+  assert_eq!(
+    Some(to_path(&["a", "B"])),
+    resolve_local_path(&module_info, &to_path(&["super", "a", "B"]))
+  );
+  assert_eq!(
+    None,
+    resolve_local_path(&module_info, &to_path(&["std", "fmt", "Debug"]))
+  );
+}
+
+#[test]
+fn check_expand_glob_imports() {
+  let root: syn::File = syn::parse_quote! {
+    pub mod types;
+    pub mod api;
+    use std::sync::Arc;
+  };
+  let types: syn::File = syn::parse_quote! {
+    pub struct Foo;
+    struct Hidden;
+  };
+  let api: syn::File = syn::parse_quote! {
+    use super::*;
+    use crate::types::*;
+    use std::collections::*;
+    pub struct Foo;
+  };
+  let mut list = vec![
+    ModuleInfo::new(vec![syn::parse_quote!(types)], types.items),
+    ModuleInfo::new(vec![syn::parse_quote!(api)], api.items),
+    ModuleInfo::new(Vec::new(), root.items),
+  ];
+  expand_glob_imports(&mut list);
+  let to_path = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+  // 親モジュールの非公開の`use`はglobで使えるが、兄弟のモジュールの非公開のアイテムは使えない。
+  // 定義された`Foo`はglobより優先される
+  assert_eq!(
+    vec![
+      ("types".to_string(), to_path(&["crate", "types"])),
+      ("api".to_string(), to_path(&["crate", "api"])),
+      ("Arc".to_string(), to_path(&["std", "sync", "Arc"])),
+    ],
+    list[1].imports
+  );
+  assert_eq!(
+    Some(to_path(&["std", "collections"])),
+    external_glob(&list[1])
+  );
+}
+
+#[test]
+fn check_canonical_path() {
+  let file: syn::File = syn::parse_quote! {
+    use std::io;
+    pub struct Error;
+  };
+  let module_info = ModuleInfo::new(Vec::new(), file.items);
+  let to_path = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+  assert_eq!(
+    to_path(&["std", "io", "Result"]),
+    canonical_path(&module_info, &to_path(&["io", "Result"]))
+  );
+  assert_eq!(
+    to_path(&["std", "string", "String"]),
+    canonical_path(&module_info, &to_path(&["String"]))
+  );
+  assert_eq!(
+    to_path(&["std", "fmt", "Result"]),
+    canonical_path(&module_info, &to_path(&["core", "fmt", "Result"]))
+  );
+  assert_eq!(
+    to_path(&["crate", "Error"]),
+    canonical_path(&module_info, &to_path(&["Error"]))
+  );
+}
+
+#[test]
+fn check_get_children_modules_ambiguous_file() {
+  let files = [("a.rs", ""), ("a/mod.rs", "")];
+  let root: syn::File = syn::parse_quote! {
+    pub mod a;
+  };
+  let result = get_children_modules(
+    &ModuleInfo::new(Vec::new(), root.items),
+    &ModDirs::default(),
+    &MemorySource(&files),
+    &CfgSet::default(),
+  );
+  assert!(result.is_err());
+}
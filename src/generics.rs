@@ -0,0 +1,427 @@
+use crate::types::{show_tokens, Change};
+use regex::{Captures, Regex};
+use syn::{GenericParam, Generics, TypeParamBound, WherePredicate};
+
+/// 境界から導かれる他の境界（標準ライブラリのsupertrait）
+const IMPLIED_BOUNDS: [(&str, &[&str]); 9] = [
+  ("Copy", &["Clone"]),
+  ("Eq", &["PartialEq"]),
+  ("Ord", &["Eq", "PartialOrd"]),
+  ("PartialOrd", &["PartialEq"]),
+  ("Fn", &["FnMut"]),
+  ("FnMut", &["FnOnce"]),
+  ("DoubleEndedIterator", &["Iterator"]),
+  ("ExactSizeIterator", &["Iterator"]),
+  ("Error", &["Debug", "Display"]),
+];
+
+/// ジェネリクスの引数の名前
+fn param_name(param: &GenericParam) -> String {
+  match param {
+    GenericParam::Lifetime(l) => l.lifetime.to_string(),
+    GenericParam::Type(t) => t.ident.to_string(),
+    GenericParam::Const(c) => c.ident.to_string(),
+  }
+}
+
+/// ジェネリクスの引数が持つデフォルト値
+fn param_default(param: &GenericParam) -> Option<String> {
+  match param {
+    GenericParam::Lifetime(_) => None,
+    GenericParam::Type(t) => t.default.as_ref().map(show_tokens),
+    GenericParam::Const(c) => c.default.as_ref().map(show_tokens),
+  }
+}
+
+/// ジェネリクスの引数を比較する
+///
+/// 引数は位置で対応付けるので、名前を変えただけなら互換性がある
+pub(crate) fn generics_changes(old_generics: &Generics, new_generics: &Generics) -> Vec<Change> {
+  let mut changes = Vec::new();
+  // ライフタイムとそれ以外は別々に指定するので分けて比較する
+  let (old_lifetimes, old_params): (Vec<_>, Vec<_>) = old_generics
+    .params
+    .iter()
+    .partition(|p| matches!(p, GenericParam::Lifetime(_)));
+  let (new_lifetimes, new_params): (Vec<_>, Vec<_>) = new_generics
+    .params
+    .iter()
+    .partition(|p| matches!(p, GenericParam::Lifetime(_)));
+
+  for lifetime in new_lifetimes.iter().skip(old_lifetimes.len()) {
+    changes.push(Change::breaking(format!(
+      "lifetime parameter `{}` was added",
+      param_name(lifetime)
+    )));
+  }
+  for lifetime in old_lifetimes.iter().skip(new_lifetimes.len()) {
+    changes.push(Change::breaking(format!(
+      "lifetime parameter `{}` was removed",
+      param_name(lifetime)
+    )));
+  }
+
+  for (i, old_param) in old_params.iter().enumerate() {
+    let old_name = param_name(old_param);
+    let Some(new_param) = new_params.get(i) else {
+      changes.push(Change::breaking(format!(
+        "generic parameter `{old_name}` was removed"
+      )));
+      continue;
+    };
+    let new_name = param_name(new_param);
+    if old_name != new_name
+      && old_params.iter().any(|p| param_name(p) == new_name)
+      && new_params.iter().any(|p| param_name(p) == old_name)
+    {
+      changes.push(Change::breaking(format!(
+        "generic parameters were reordered: `{old_name}` is now at position {}",
+        new_params
+          .iter()
+          .position(|p| param_name(p) == old_name)
+          .unwrap()
+      )));
+      continue;
+    }
+    match (old_param, new_param) {
+      (GenericParam::Type(_), GenericParam::Type(_)) => (),
+      (GenericParam::Const(old_const), GenericParam::Const(new_const)) => {
+        if old_const.ty != new_const.ty {
+          changes.push(Change::breaking(format!(
+            "type of const parameter `{old_name}` changed: {} -> {}",
+            show_tokens(&old_const.ty),
+            show_tokens(&new_const.ty)
+          )));
+        }
+      }
+      _ => {
+        changes.push(Change::breaking(format!(
+          "generic parameter `{old_name}` changed: `{}` -> `{}`",
+          show_tokens(*old_param),
+          show_tokens(*new_param)
+        )));
+        continue;
+      }
+    }
+    match (param_default(old_param), param_default(new_param)) {
+      (Some(old_default), Some(new_default)) if old_default != new_default => {
+        changes.push(Change::breaking(format!(
+          "default of generic parameter `{old_name}` changed: {old_default} -> {new_default}"
+        )))
+      }
+      (Some(_), None) => changes.push(Change::breaking(format!(
+        "default of generic parameter `{old_name}` was removed"
+      ))),
+      (None, Some(new_default)) => changes.push(Change::minor(format!(
+        "default `{new_default}` was added to generic parameter `{old_name}`"
+      ))),
+      _ => (),
+    }
+  }
+  for new_param in new_params.iter().skip(old_params.len()) {
+    if param_default(new_param).is_some() {
+      changes.push(Change::minor(format!(
+        "generic parameter `{}` with default was added",
+        show_tokens(*new_param)
+      )));
+    } else {
+      changes.push(Change::breaking(format!(
+        "generic parameter `{}` was added without a default",
+        param_name(new_param)
+      )));
+    }
+  }
+  changes.extend(bounds_changes(old_generics, new_generics));
+  changes
+}
+
+/// 正規化した境界
+struct Bound {
+  /// 境界を付けられた型（引数の名前は位置に置き換える）
+  bounded: String,
+  /// 境界（引数の名前は位置に置き換える）
+  bound: String,
+  /// 表示用の元の表記
+  display: String,
+  /// 他の境界から導かれたものか
+  implied: bool,
+}
+
+/// 境界と、そこから導かれる境界を集める
+fn expand_bound(bound: &TypeParamBound) -> Vec<TypeParamBound> {
+  let mut v = vec![bound.clone()];
+  let mut i = 0;
+  while i < v.len() {
+    if let TypeParamBound::Trait(trait_bound) = v[i].clone() {
+      if let Some(segment) = trait_bound.path.segments.last() {
+        let name = segment.ident.to_string();
+        if let Some((_, implied)) = IMPLIED_BOUNDS.iter().find(|(n, _)| *n == name) {
+          for implied_name in implied.iter() {
+            let mut implied_bound = trait_bound.clone();
+            let last = implied_bound.path.segments.last_mut().unwrap();
+            last.ident = syn::Ident::new(implied_name, last.ident.span());
+            let implied_bound = TypeParamBound::Trait(implied_bound);
+            if !v.contains(&implied_bound) {
+              v.push(implied_bound);
+            }
+          }
+        }
+      }
+    }
+    i += 1;
+  }
+  v
+}
+
+/// 引数の名前を位置に置き換えた境界の一覧を作る
+///
+/// 引数に直接書いた境界と`where`節の境界は同じものとして扱う
+fn normalized_bounds(generics: &Generics) -> Vec<Bound> {
+  let mut renames = Vec::new();
+  let mut lifetime_count = 0;
+  let mut param_count = 0;
+  for param in generics.params.iter() {
+    match param {
+      GenericParam::Lifetime(l) => {
+        renames.push((l.lifetime.to_string(), format!("'L{lifetime_count}")));
+        lifetime_count += 1;
+      }
+      GenericParam::Type(t) => {
+        renames.push((t.ident.to_string(), format!("P{param_count}")));
+        param_count += 1;
+      }
+      GenericParam::Const(c) => {
+        renames.push((c.ident.to_string(), format!("P{param_count}")));
+        param_count += 1;
+      }
+    }
+  }
+  let re = Regex::new(r"'?[A-Za-z_][A-Za-z0-9_]*").unwrap();
+  let rename = |s: &str| {
+    re.replace_all(s, |caps: &Captures| {
+      let word = &caps[0];
+      renames
+        .iter()
+        .find(|(from, _)| from == word)
+        .map(|(_, to)| to.clone())
+        .unwrap_or_else(|| word.to_string())
+    })
+    .to_string()
+  };
+
+  let mut pairs = Vec::new();
+  for param in generics.params.iter() {
+    match param {
+      GenericParam::Lifetime(l) => {
+        for bound in l.bounds.iter() {
+          pairs.push((
+            l.lifetime.to_string(),
+            show_tokens(bound),
+            vec![show_tokens(bound)],
+          ));
+        }
+      }
+      GenericParam::Type(t) => {
+        for bound in t.bounds.iter() {
+          pairs.push((
+            t.ident.to_string(),
+            show_tokens(bound),
+            expand_bound(bound).iter().map(show_tokens).collect(),
+          ));
+        }
+      }
+      GenericParam::Const(_) => (),
+    }
+  }
+  if let Some(where_clause) = &generics.where_clause {
+    for predicate in where_clause.predicates.iter() {
+      match predicate {
+        WherePredicate::Lifetime(pl) => {
+          for bound in pl.bounds.iter() {
+            pairs.push((
+              pl.lifetime.to_string(),
+              show_tokens(bound),
+              vec![show_tokens(bound)],
+            ));
+          }
+        }
+        WherePredicate::Type(pt) => {
+          let bounded = match &pt.lifetimes {
+            Some(lifetimes) => {
+              format!("{} {}", show_tokens(lifetimes), show_tokens(&pt.bounded_ty))
+            }
+            None => show_tokens(&pt.bounded_ty),
+          };
+          for bound in pt.bounds.iter() {
+            pairs.push((
+              bounded.clone(),
+              show_tokens(bound),
+              expand_bound(bound).iter().map(show_tokens).collect(),
+            ));
+          }
+        }
+        _ => (),
+      }
+    }
+  }
+
+  let mut v: Vec<Bound> = Vec::new();
+  for (bounded, display_bound, bounds) in pairs.iter() {
+    for (i, bound) in bounds.iter().enumerate() {
+      let normalized = Bound {
+        bounded: rename(bounded),
+        bound: rename(bound),
+        display: format!("{bounded}: {display_bound}"),
+        implied: i != 0,
+      };
+      if !v
+        .iter()
+        .any(|b| b.bounded == normalized.bounded && b.bound == normalized.bound)
+      {
+        v.push(normalized);
+      }
+    }
+  }
+  v
+}
+
+/// 境界を比較する
+///
+/// 新しい側で要求が強くなっていれば非互換、弱くなっていれば互換性のある変更とする
+fn bounds_changes(old_generics: &Generics, new_generics: &Generics) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let old_bounds = normalized_bounds(old_generics);
+  let new_bounds = normalized_bounds(new_generics);
+  let contains = |bounds: &[Bound], b: &Bound| {
+    bounds
+      .iter()
+      .any(|x| x.bounded == b.bounded && x.bound == b.bound)
+  };
+  // 導かれた境界を含めた集合で比較し、直接書かれた境界だけを報告する
+  for new_bound in new_bounds.iter().filter(|b| !b.implied) {
+    if contains(&old_bounds, new_bound) {
+      continue;
+    }
+    // `?Sized`は要求を緩める境界
+    if new_bound.bound.starts_with('?') {
+      changes.push(Change::minor(format!(
+        "bound `{}` was added",
+        new_bound.display
+      )));
+    } else {
+      changes.push(Change::breaking(format!(
+        "bound `{}` was added",
+        new_bound.display
+      )));
+    }
+  }
+  for old_bound in old_bounds.iter().filter(|b| !b.implied) {
+    if contains(&new_bounds, old_bound) {
+      continue;
+    }
+    if old_bound.bound.starts_with('?') {
+      changes.push(Change::breaking(format!(
+        "bound `{}` was removed",
+        old_bound.display
+      )));
+    } else {
+      changes.push(Change::minor(format!(
+        "bound `{}` was removed",
+        old_bound.display
+      )));
+    }
+  }
+  changes
+}
+
+#[test]
+fn check_generics_changes() {
+  let severities = |old: Generics, new: Generics| {
+    generics_changes(&old, &new)
+      .iter()
+      .map(|c| c.severity)
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    Vec::<crate::types::Severity>::new(),
+    severities(syn::parse_quote!(<'a, T>), syn::parse_quote!(<'b, U>))
+  );
+  assert_eq!(
+    vec![crate::types::Severity::Minor],
+    severities(syn::parse_quote!(<T>), syn::parse_quote!(<T, A = ()>))
+  );
+  assert_eq!(
+    vec![
+      crate::types::Severity::Breaking,
+      crate::types::Severity::Breaking
+    ],
+    severities(syn::parse_quote!(<A, B>), syn::parse_quote!(<B, A>))
+  );
+  assert_eq!(
+    vec![crate::types::Severity::Breaking],
+    severities(
+      syn::parse_quote!(<const N: usize>),
+      syn::parse_quote!(<const N: u32>)
+    )
+  );
+}
+
+#[test]
+fn check_bounds_changes() {
+  let severities = |old: syn::ItemFn, new: syn::ItemFn| {
+    generics_changes(&old.sig.generics, &new.sig.generics)
+      .iter()
+      .map(|c| c.severity)
+      .collect::<Vec<_>>()
+  };
+  // 直接書いた境界と`where`節の境界は同じ
+  assert_eq!(
+    Vec::<crate::types::Severity>::new(),
+    severities(
+      syn::parse_quote!(
+        fn f<T: Clone>() {}
+      ),
+      syn::parse_quote!(
+        fn f<U>()
+        where
+          U: Clone,
+        {
+        }
+      )
+    )
+  );
+  assert_eq!(
+    vec![crate::types::Severity::Breaking],
+    severities(
+      syn::parse_quote!(
+        fn f<T: Clone>() {}
+      ),
+      syn::parse_quote!(
+        fn f<T: Clone + Send>() {}
+      )
+    )
+  );
+  // `Copy`は`Clone`を含むので、`Clone`を`Copy`にするのは要求を強くする変更
+  assert_eq!(
+    vec![crate::types::Severity::Breaking],
+    severities(
+      syn::parse_quote!(
+        fn f<T: Clone>() {}
+      ),
+      syn::parse_quote!(
+        fn f<T: Copy>() {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![crate::types::Severity::Minor],
+    severities(
+      syn::parse_quote!(
+        fn f<T: Copy>() {}
+      ),
+      syn::parse_quote!(
+        fn f<T: Clone>() {}
+      )
+    )
+  );
+}
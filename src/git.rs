@@ -1,7 +1,6 @@
 use anyhow::{anyhow, Context};
 use git2::{Repository, Tree};
 use std::path::{Path, PathBuf};
-use syn::Ident;
 
 pub(crate) fn find_repo(dir: &Path) -> anyhow::Result<(usize, Repository)> {
   // 見つからなかったら階層を親側に辿る
@@ -66,30 +65,3 @@ pub(crate) fn get_file_contents(
   let contents = String::from_utf8(blob.content().to_vec())?;
   Ok(contents)
 }
-
-pub(crate) fn get_mod_file(
-  git_path_prefix: &Option<PathBuf>,
-  p: &[Ident],
-  tree: &Tree,
-  repo: &Repository,
-) -> anyhow::Result<String> {
-  let p = p
-    .iter()
-    .map(|i| i.to_string())
-    .collect::<Vec<_>>()
-    .join("/");
-  let file_name_e2015 = Path::new(&p).join("mod.rs");
-  let mut file_name_e2018 = Path::new(&p).to_path_buf();
-  file_name_e2018.set_extension("rs");
-  if let Ok(file) = get_file_contents(git_path_prefix, &file_name_e2015, tree, repo) {
-    Ok(file)
-  } else if let Ok(file) = get_file_contents(git_path_prefix, &file_name_e2018, tree, repo) {
-    Ok(file)
-  } else {
-    Err(anyhow!(
-      "Failed get module entry file: {} or {}",
-      file_name_e2015.display(),
-      file_name_e2018.display()
-    ))
-  }
-}
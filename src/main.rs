@@ -1,11 +1,17 @@
 use anyhow::{anyhow, Context};
 use clap::Parser;
-use std::fs;
+use source::ModuleSource;
 use std::path::Path;
 
+mod attrs;
+mod cfg;
+mod exports;
 mod file;
+mod generics;
 mod git;
 mod metadata;
+mod normalize;
+mod source;
 mod types;
 
 #[derive(Debug, Clone, Parser)]
@@ -16,12 +22,398 @@ struct Args {
   /// Git Object ID
   #[clap(short, long)]
   pub oid: Option<String>,
+  /// Enable a cfg option when evaluating `#[cfg(...)]`, such as `test` or `target_os="windows"`.
+  /// Single-valued options like `target_os` replace the host's value
+  #[clap(long = "cfg", value_name = "SPEC")]
+  pub cfgs: Vec<String>,
+  /// Space or comma separated list of features to activate
+  #[clap(short = 'F', long)]
+  pub features: Vec<String>,
+  /// Activate all available features
+  #[clap(long)]
+  pub all_features: bool,
+  /// Do not activate the `default` feature
+  #[clap(long)]
+  pub no_default_features: bool,
+  /// Compare the API under every combination of the features of both manifests
+  #[clap(long, conflicts_with_all = ["features", "all_features", "no_default_features"])]
+  pub feature_matrix: bool,
+}
+
+/// 表示用のモジュールのパス
+fn show_mod_path<T: ToString>(mod_path: &[T]) -> String {
+  if mod_path.is_empty() {
+    "lib".to_string()
+  } else {
+    mod_path
+      .iter()
+      .map(|i| i.to_string())
+      .collect::<Vec<_>>()
+      .join("/")
+  }
+}
+
+/// モジュールごとにアイテムを取り出し、比較できる形に揃える
+///
+/// 結果は`module_info_list`と同じ順に並ぶ
+fn prepare_item_type_data(
+  module_info_list: &[file::ModuleInfo],
+  aliases: &[types::TypeAliasData],
+  surface: &exports::PublicSurface,
+  dyn_usage: &[(Vec<String>, String)],
+) -> Vec<Vec<types::ItemTypeData>> {
+  module_info_list
+    .iter()
+    .map(|module_info| {
+      let mut item_type_data = types::extract_types(&module_info.items);
+      types::mark_sealed_traits(&mut item_type_data, module_info, module_info_list, surface);
+      types::mark_dyn_usage(&mut item_type_data, module_info, dyn_usage);
+      for data in item_type_data.iter_mut() {
+        types::canonicalize_types(data, module_info);
+        types::expand_type_aliases(data, aliases);
+        types::publicize_paths(data, surface);
+        normalize::normalize_types(data);
+      }
+      item_type_data
+    })
+    .collect()
+}
+
+/// 公開されたパスが指すアイテムを、公開された名前で取り出す
+fn public_item_data(
+  module_info_list: &[file::ModuleInfo],
+  item_type_data: &[Vec<types::ItemTypeData>],
+  item: &exports::PublicItem,
+) -> Vec<types::ItemTypeData> {
+  let Some(index) = module_info_list
+    .iter()
+    .position(|m| exports::mod_path_strings(m) == item.def_mod_path)
+  else {
+    return Vec::new();
+  };
+  let name = item.path.last().unwrap();
+  item_type_data[index]
+    .iter()
+    .filter(|data| data.ident() == &item.def_name)
+    .cloned()
+    .map(|mut data| {
+      if name != &item.def_name {
+        let ident = types::ident_from_str(name, data.ident());
+        data.set_ident(ident);
+      }
+      data
+    })
+    .collect()
+}
+
+/// 報告する1件の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ReportEntry {
+  /// `Uncompatible: ...`のような見出し
+  header: String,
+  /// 検出した変更点
+  changes: Vec<String>,
+}
+
+impl ReportEntry {
+  fn new(header: String) -> Self {
+    ReportEntry {
+      header,
+      changes: Vec::new(),
+    }
+  }
+}
+
+/// 互換性の判定結果を報告に加える
+fn push_result(
+  report: &mut Vec<ReportEntry>,
+  name: &str,
+  result: types::ResultDetermineCompatibility,
+) {
+  match result {
+    types::ResultDetermineCompatibility::Uncompatible(_new_data, changes) => {
+      report.push(ReportEntry {
+        header: format!("Uncompatible: {name}"),
+        changes: show_changes(&changes),
+      });
+    }
+    types::ResultDetermineCompatibility::Compatible(changes) => {
+      report.push(ReportEntry {
+        header: format!("Compatible: {name}"),
+        changes: show_changes(&changes),
+      });
+    }
+    types::ResultDetermineCompatibility::NotFound => {
+      report.push(ReportEntry::new(format!(
+        "Uncompatible: {name} does not exist"
+      )));
+    }
+    types::ResultDetermineCompatibility::Ok => {}
+  }
+}
+
+/// 検出した変更点を表示用の文字列にする
+fn show_changes(changes: &[types::Change]) -> Vec<String> {
+  changes
+    .iter()
+    .map(|change| {
+      let label = match change.severity {
+        types::Severity::Breaking => "breaking",
+        types::Severity::Minor => "minor",
+        types::Severity::Info => "info",
+      };
+      format!("{label}: {}", change.message)
+    })
+    .collect()
+}
+
+/// 報告を表示する
+fn print_report(report: &[ReportEntry]) {
+  for entry in report.iter() {
+    println!("{}", entry.header);
+    for change in entry.changes.iter() {
+      println!("  {change}");
+    }
+  }
+}
+
+/// featureの組み合わせごとの報告をまとめ、どの組み合わせで起きたかと一緒に表示する
+fn print_feature_reports(reports: &[(metadata::FeatureSelection, Vec<ReportEntry>)]) {
+  let mut grouped: Vec<(&ReportEntry, Vec<String>)> = Vec::new();
+  for (selection, report) in reports.iter() {
+    for entry in report.iter() {
+      match grouped.iter_mut().find(|(e, _)| *e == entry) {
+        Some((_, features)) => features.push(selection.show()),
+        None => grouped.push((entry, vec![selection.show()])),
+      }
+    }
+  }
+  for (entry, features) in grouped {
+    print_report(std::slice::from_ref(entry));
+    if features.len() == reports.len() {
+      println!("  under: all feature combinations");
+    } else {
+      println!("  under: {}", features.join(" | "));
+    }
+  }
+}
+
+/// 新旧のモジュールの公開APIを比較する
+fn compare_modules(
+  old_module_info_list: &[file::ModuleInfo],
+  new_module_info_list: &[file::ModuleInfo],
+) -> Vec<ReportEntry> {
+  let mut report = Vec::new();
+  // 型エイリアスは展開してから比較する
+  let old_aliases = types::extract_type_aliases(old_module_info_list);
+  let new_aliases = types::extract_type_aliases(new_module_info_list);
+
+  // 定義されたモジュールではなく、公開されたパスから到達できるものを比較する
+  let old_surface = exports::public_surface(old_module_info_list);
+  let new_surface = exports::public_surface(new_module_info_list);
+
+  // 公開シグネチャの中で`dyn Trait`として使われているtraitを集める
+  let mut old_dyn_usage = Vec::new();
+  for old_item in old_surface.items.iter() {
+    let (_, mod_path) = old_item.path.split_last().unwrap();
+    let Some(old_module_info) = exports::find_module(old_module_info_list, &old_item.def_mod_path)
+    else {
+      continue;
+    };
+    for data in types::extract_types(&old_module_info.items)
+      .iter()
+      .filter(|data| data.ident() == &old_item.def_name)
+    {
+      for path in types::dyn_trait_paths(data, old_module_info) {
+        // 再エクスポートされたパスで書かれていても、定義されている場所で比べる
+        let def = old_surface.def_of(&path).unwrap_or(path);
+        old_dyn_usage.push((
+          def,
+          format!("{}::({})", show_mod_path(mod_path), data.show_name()),
+        ));
+      }
+    }
+  }
+
+  let old_item_type_data = prepare_item_type_data(
+    old_module_info_list,
+    &old_aliases,
+    &old_surface,
+    &old_dyn_usage,
+  );
+  let new_item_type_data =
+    prepare_item_type_data(new_module_info_list, &new_aliases, &new_surface, &[]);
+
+  for mod_path in old_surface.modules.iter() {
+    if !new_surface.modules.contains(mod_path) {
+      report.push(ReportEntry::new(format!(
+        "Uncompatible: {} module does not exist",
+        show_mod_path(mod_path)
+      )));
+    }
+  }
+  for old_item in old_surface.items.iter() {
+    let (_, mod_path) = old_item.path.split_last().unwrap();
+    // モジュールごと無くなった場合は既に報告されている
+    if !new_surface.modules.iter().any(|m| m == mod_path) {
+      continue;
+    }
+    let new_data = new_surface
+      .items
+      .iter()
+      .filter(|i| i.path == old_item.path)
+      .flat_map(|i| public_item_data(new_module_info_list, &new_item_type_data, i))
+      .collect::<Vec<_>>();
+    for old_data in public_item_data(old_module_info_list, &old_item_type_data, old_item) {
+      let name = format!("{}::({})", show_mod_path(mod_path), old_data.show_name());
+      if new_data.is_empty() && new_surface.ambiguous.contains(&old_item.path) {
+        report.push(ReportEntry::new(format!(
+          "Uncompatible: {name} became ambiguous between glob re-exports"
+        )));
+        continue;
+      }
+      let result = types::determine_compatibility(&old_data, &new_data);
+      push_result(&mut report, &name, result);
+    }
+  }
+
+  // 外部クレートのものの再エクスポートは、パスと指しているものだけを比較する
+  for old_reexport in old_surface.external.iter() {
+    let (name, mod_path) = old_reexport.path.split_last().unwrap();
+    // モジュールごと無くなった場合は既に報告されている
+    if new_surface.external.contains(old_reexport)
+      || !new_surface.modules.iter().any(|m| m == mod_path)
+    {
+      continue;
+    }
+    let target = old_reexport.target.join("::");
+    let use_name = if old_reexport.target.last() == Some(name) {
+      format!("use {target}")
+    } else {
+      format!("use {target} as {name}")
+    };
+    let header = format!("{}::({use_name})", show_mod_path(mod_path));
+    let new_target = new_surface
+      .external
+      .iter()
+      .find(|e| e.path == old_reexport.path)
+      .map(|e| e.target.join("::"))
+      .or_else(|| {
+        new_surface
+          .def_of(&old_reexport.path)
+          .map(|def| format!("crate::{}", def.join("::")))
+      });
+    match new_target {
+      Some(new_target) => report.push(ReportEntry {
+        header: format!("Uncompatible: {header}"),
+        changes: show_changes(&[types::Change::breaking(format!(
+          "`{name}` now refers to `{new_target}` instead of `{target}`"
+        ))]),
+      }),
+      None => push_result(
+        &mut report,
+        &header,
+        types::ResultDetermineCompatibility::NotFound,
+      ),
+    }
+  }
+
+  // globでimportされるモジュールに追加された名前は、利用者側の他のglobと衝突しうる
+  for new_item in new_surface.items.iter() {
+    let (item_name, mod_path) = new_item.path.split_last().unwrap();
+    if !new_surface.glob_modules.iter().any(|m| m == mod_path)
+      || old_surface.items.iter().any(|i| i.path == new_item.path)
+    {
+      continue;
+    }
+    let glob = mod_path
+      .iter()
+      .map(|s| s.as_str())
+      .chain(["*"])
+      .collect::<Vec<_>>()
+      .join("::");
+    let mut message = format!(
+      "`{item_name}` was added to a glob-imported module: downstream `use {glob}` may become ambiguous with other glob imports"
+    );
+    if file::is_prelude_name(item_name) {
+      message.push_str(&format!(
+        ", and it shadows the standard prelude's `{item_name}`"
+      ));
+    }
+    for new_data in public_item_data(new_module_info_list, &new_item_type_data, new_item) {
+      push_result(
+        &mut report,
+        &format!("{}::({})", show_mod_path(mod_path), new_data.show_name()),
+        types::ResultDetermineCompatibility::Compatible(vec![types::Change::minor(
+          message.clone(),
+        )]),
+      );
+    }
+  }
+
+  // 固有実装のメソッドや関連定数を型ごとに比較する
+  let old_impls = types::extract_inherent_impls(old_module_info_list, &old_aliases, &old_surface);
+  let new_impls = types::extract_inherent_impls(new_module_info_list, &new_aliases, &new_surface);
+  for old_impl in old_impls.iter() {
+    // 型に到達できない場合は比較しない
+    let Some(public_path) = old_surface
+      .def_of(&old_impl.self_path)
+      .and_then(|def| old_surface.public_path_of(&def))
+    else {
+      continue;
+    };
+    // 型そのものが無くなった場合は既に報告されている
+    let Some(new_def) = new_surface.def_of(public_path) else {
+      continue;
+    };
+    let new_items = new_impls
+      .iter()
+      .filter(|i| new_surface.def_of(&i.self_path).as_ref() == Some(&new_def))
+      .flat_map(|i| i.items.iter().cloned())
+      .collect::<Vec<_>>();
+    let (type_name, mod_path) = public_path.split_last().unwrap();
+    let mod_path_str = show_mod_path(mod_path);
+    for old_data in old_impl.items.iter() {
+      let result = types::determine_compatibility(old_data, &new_items);
+      push_result(
+        &mut report,
+        &format!("{mod_path_str}::{type_name}::({})", old_data.show_name()),
+        result,
+      );
+    }
+  }
+
+  // 公開されている型やtraitについてのtraitの実装が無くなっていないかを調べる
+  let old_trait_impls =
+    types::extract_trait_impls(old_module_info_list, &old_aliases, &old_surface);
+  let new_trait_impls =
+    types::extract_trait_impls(new_module_info_list, &new_aliases, &new_surface);
+  for old_impl in old_trait_impls.iter() {
+    // 非公開のtraitや型を含む実装と、traitや型そのものが無くなった場合は比較しない
+    let Some(public_paths) = old_impl.public_paths(&old_surface) else {
+      continue;
+    };
+    if !public_paths.iter().all(|p| new_surface.def_of(p).is_some()) {
+      continue;
+    }
+    if !new_trait_impls.iter().any(|i| i.key == old_impl.key) {
+      report.push(ReportEntry::new(format!(
+        "Uncompatible: ({}) was removed",
+        old_impl.name
+      )));
+    }
+  }
+  report
 }
 
 fn main() -> anyhow::Result<()> {
   let args = Args::parse();
   let dir = args.dir.unwrap_or(".".to_string());
   let dir = Path::new(&dir);
+  let mut cfg = cfg::CfgSet::host();
+  for spec in args.cfgs.iter() {
+    cfg.insert_spec(spec);
+  }
 
   // Cargo.tomlから情報を取る
   let (manifest_dep, manifest_file) = metadata::find_manifest_file(dir)?;
@@ -51,101 +443,92 @@ fn main() -> anyhow::Result<()> {
   if manifest.workspace.is_some() {
     return Err(anyhow!("Not supported for workspaces functionality"));
   }
-  let lib_file_path = metadata::lib_file_path(&manifest);
-  let pwd = manifest_file.parent().unwrap();
-  let lib_file_result = std::fs::read_to_string(pwd.join(&lib_file_path));
-  if let Ok(lib_file) = lib_file_result {
-    let lib_file_path = pwd.join(lib_file_path);
-    let pwd = &lib_file_path.parent().unwrap();
-
-    let old_manifest_file =
-      git::get_file_contents(&git_path_prefix, Path::new("Cargo.toml"), &tree, &git_repo)?;
-    let old_manifest = metadata::get_manifest_data_from_contents(&old_manifest_file)?;
-    let old_lib_file_path = metadata::lib_file_path(&old_manifest);
-    if let Ok(old_lib_file) = git::get_file_contents(
-      &git_path_prefix,
-      Path::new(&old_lib_file_path),
-      &tree,
-      &git_repo,
-    ) {
-      let new_lib_file = syn::parse_file(&lib_file)?;
-      let new_target_module_info = file::ModuleInfo {
-        mod_path: Vec::new(),
-        items: new_lib_file.items,
-      };
-      let mut new_module_info_list = file::get_children_modules(&new_target_module_info, &|p| {
-        let file_path = file::check_mod_file_exists(pwd, p);
-        match file_path {
-          Ok(file_path) => {
-            let contents = fs::read_to_string(&file_path);
-            match contents {
-              Ok(contents) => Ok(contents),
-              Err(e) => Err(e.into()),
-            }
-          }
-          Err(e) => Err(e),
-        }
-      })?;
-      new_module_info_list.push(new_target_module_info);
-
-      let pwd_old_lib = Path::new(&old_lib_file_path).parent();
-      let src_git_path_prefix = match (&git_path_prefix, pwd_old_lib) {
-        (Some(git), Some(pwd)) => Some(git.join(pwd)),
-        (Some(git), None) => Some(git.clone()),
-        (None, Some(pwd)) => Some(pwd.to_path_buf()),
-        (None, None) => None,
-      };
-      let old_lib_file = syn::parse_file(&old_lib_file)?;
-      let old_target_module_info = file::ModuleInfo {
-        mod_path: Vec::new(),
-        items: old_lib_file.items,
-      };
-      let mut old_module_info_list = file::get_children_modules(&old_target_module_info, &|p| {
-        git::get_mod_file(&src_git_path_prefix, p, &tree, &git_repo)
-      })?;
-      old_module_info_list.push(old_target_module_info);
-
-      for old_module_info in old_module_info_list.iter() {
-        let mod_path_str = if old_module_info.mod_path.is_empty() {
-          "lib".to_string()
-        } else {
-          old_module_info
-            .mod_path
-            .iter()
-            .map(|i| i.to_string())
-            .collect::<Vec<_>>()
-            .join("/")
-        };
-        if let Some(new_module_info) = new_module_info_list
-          .iter()
-          .find(|info| info.mod_path == old_module_info.mod_path)
-        {
-          let old_item_type_data = types::extract_types(&old_module_info.items);
-          let new_item_type_data = types::extract_types(&new_module_info.items);
-          for old_data in old_item_type_data.iter() {
-            let result = types::determine_compatibility(old_data, &new_item_type_data);
-            match result {
-              types::ResultDetermineCompatibility::Uncompatible(_new_data) => {
-                println!("Uncompatible: {mod_path_str}::({})", old_data.show_name());
-              }
-              types::ResultDetermineCompatibility::NotFound => {
-                println!(
-                  "Uncompatible: {mod_path_str}::({}) does not exist",
-                  old_data.show_name()
-                );
-              }
-              types::ResultDetermineCompatibility::Ok => {}
-            }
-          }
-        } else {
-          println!("Uncompatible: {mod_path_str} module does not exist")
-        }
-      }
-    } else {
-      return Ok(());
+  let lib_file_path = manifest_file
+    .parent()
+    .unwrap()
+    .join(metadata::lib_file_path(&manifest));
+  let new_source = source::DirSource {
+    root: lib_file_path.parent().unwrap().to_path_buf(),
+  };
+  let lib_file_name = Path::new(
+    lib_file_path
+      .file_name()
+      .ok_or_else(|| anyhow!("Invalid library path: {}", lib_file_path.display()))?,
+  );
+  if new_source.read_file(lib_file_name)?.is_none() {
+    return Ok(());
+  }
+
+  let old_manifest_file =
+    git::get_file_contents(&git_path_prefix, Path::new("Cargo.toml"), &tree, &git_repo)?;
+  let old_manifest = metadata::get_manifest_data_from_contents(&old_manifest_file)?;
+  let old_lib_file_path = metadata::lib_file_path(&old_manifest);
+  let old_lib_file_path = Path::new(&old_lib_file_path);
+  let src_git_path_prefix = match (&git_path_prefix, old_lib_file_path.parent()) {
+    (Some(git), Some(pwd)) => Some(git.join(pwd)),
+    (Some(git), None) => Some(git.clone()),
+    (None, Some(pwd)) => Some(pwd.to_path_buf()),
+    (None, None) => None,
+  };
+  let old_source = source::GitTreeSource {
+    repo: &git_repo,
+    tree: &tree,
+    root: src_git_path_prefix,
+  };
+  let old_lib_file_name = Path::new(
+    old_lib_file_path
+      .file_name()
+      .ok_or_else(|| anyhow!("Invalid library path: {}", old_lib_file_path.display()))?,
+  );
+
+  let selections = if args.feature_matrix {
+    // 片方にしか無いfeatureもあるので、無いものは黙って無視する
+    metadata::feature_matrix(&old_manifest, &manifest)
+  } else {
+    let selection = metadata::FeatureSelection {
+      features: args
+        .features
+        .iter()
+        .flat_map(|f| f.split([' ', ',']))
+        .filter(|f| !f.is_empty())
+        .map(str::to_string)
+        .collect(),
+      all_features: args.all_features,
+      no_default_features: args.no_default_features,
+    };
+    selection.check_defined(&old_manifest, &manifest)?;
+    vec![selection]
+  };
+  let mut reports = Vec::new();
+  for selection in selections {
+    // featureは新旧それぞれのマニフェストの定義に従って有効にする
+    let mut old_cfg = cfg.clone();
+    for feature in selection.resolve(&old_manifest) {
+      old_cfg.enable_feature(&feature);
+    }
+    let mut new_cfg = cfg.clone();
+    for feature in selection.resolve(&manifest) {
+      new_cfg.enable_feature(&feature);
     }
+    let Some(new_module_info_list) =
+      file::load_crate_modules(&new_source, lib_file_name, &new_cfg)?
+    else {
+      return Ok(());
+    };
+    let Some(old_module_info_list) =
+      file::load_crate_modules(&old_source, old_lib_file_name, &old_cfg)?
+    else {
+      return Ok(());
+    };
+    let report = compare_modules(&old_module_info_list, &new_module_info_list);
+    reports.push((selection, report));
+  }
+  if args.feature_matrix {
+    print_feature_reports(&reports);
   } else {
-    return Ok(());
+    for (_, report) in reports.iter() {
+      print_report(report);
+    }
   }
   Ok(())
 }
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Context};
 use cargo_manifest::Manifest;
 use cargo_metadata::{Metadata, MetadataCommand};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -40,6 +41,164 @@ pub(crate) fn lib_file_path(manifest: &Manifest) -> String {
   }
 }
 
+/// `[features]`の定義
+///
+/// `dep:`で参照されていないoptionalな依存も、同じ名前のfeatureとして含める
+pub(crate) fn feature_table(manifest: &Manifest) -> BTreeMap<String, Vec<String>> {
+  let mut table = manifest.features.clone().unwrap_or_default();
+  let optional_deps = manifest
+    .dependencies
+    .iter()
+    .chain(manifest.build_dependencies.iter())
+    .flatten()
+    .chain(manifest.target.iter().flatten().flat_map(|(_, target)| {
+      target
+        .dependencies
+        .iter()
+        .chain(target.build_dependencies.iter())
+    }))
+    .filter(|(_, dep)| dep.optional())
+    .map(|(name, _)| name.clone())
+    .collect::<Vec<_>>();
+  for name in optional_deps {
+    let dep_name = format!("dep:{name}");
+    if !table.values().flatten().any(|f| f == &dep_name) {
+      table.entry(name).or_insert_with(|| vec![dep_name]);
+    }
+  }
+  table
+}
+
+/// 比較するときに有効にするfeatureの指定
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FeatureSelection {
+  pub features: Vec<String>,
+  pub all_features: bool,
+  pub no_default_features: bool,
+}
+
+impl FeatureSelection {
+  /// マニフェストの`[features]`をたどって、有効になるfeatureを全て求める
+  ///
+  /// マニフェストに無いfeatureは無視する
+  pub(crate) fn resolve(&self, manifest: &Manifest) -> Vec<String> {
+    let table = feature_table(manifest);
+    let mut stack = if self.all_features {
+      table.keys().cloned().collect::<Vec<_>>()
+    } else {
+      self.features.clone()
+    };
+    if !self.no_default_features {
+      stack.push("default".to_string());
+    }
+    let mut v: Vec<String> = Vec::new();
+    while let Some(name) = stack.pop() {
+      let Some(enabled) = table.get(&name) else {
+        continue;
+      };
+      if v.contains(&name) {
+        continue;
+      }
+      for f in enabled.iter() {
+        if f.starts_with("dep:") {
+          continue;
+        }
+        // `dep/feature`は`dep`も有効にするが、`dep?/feature`は有効にしない
+        match f.split_once('/') {
+          Some((dep, _)) if !dep.ends_with('?') => stack.push(dep.to_string()),
+          Some(_) => (),
+          None => stack.push(f.clone()),
+        }
+      }
+      v.push(name);
+    }
+    v.sort();
+    v
+  }
+
+  /// 指定されたfeatureのうち、新旧どちらのマニフェストにも無いものがあればエラーにする
+  ///
+  /// `dep/feature`の形の指定は依存のfeatureなので調べない
+  pub(crate) fn check_defined(&self, old: &Manifest, new: &Manifest) -> anyhow::Result<()> {
+    let old_table = feature_table(old);
+    let new_table = feature_table(new);
+    let unknown = self
+      .features
+      .iter()
+      .filter(|name| {
+        !name.contains('/') && !old_table.contains_key(*name) && !new_table.contains_key(*name)
+      })
+      .map(|name| format!("`{name}`"))
+      .collect::<Vec<_>>();
+    if unknown.is_empty() {
+      Ok(())
+    } else {
+      Err(anyhow!(
+        "Unknown features: {}: not defined in the old or the new Cargo.toml",
+        unknown.join(", ")
+      ))
+    }
+  }
+
+  /// 表示用の名前
+  pub(crate) fn show(&self) -> String {
+    if self.all_features {
+      return "all features".to_string();
+    }
+    let mut v = Vec::new();
+    if !self.no_default_features {
+      v.push("default");
+    }
+    v.extend(self.features.iter().map(String::as_str));
+    if v.is_empty() {
+      "no features".to_string()
+    } else {
+      v.join(", ")
+    }
+  }
+}
+
+/// featureの組み合わせの数がこれより大きくなる場合は、featureを一つずつ有効にする
+const MAX_FEATURE_POWERSET: usize = 8;
+
+/// 比較するfeatureの組み合わせを、新旧のマニフェストの`[features]`から作る
+///
+/// 既定のfeatureを有効にしたものを必ず含め、それ以外の組み合わせはdefaultを無効にした上で作る。
+/// featureが多い場合は、全て無効、一つずつ有効、全て有効の組み合わせだけにする
+pub(crate) fn feature_matrix(old: &Manifest, new: &Manifest) -> Vec<FeatureSelection> {
+  let mut names = feature_table(old)
+    .into_keys()
+    .chain(feature_table(new).into_keys())
+    .filter(|name| name != "default")
+    .collect::<Vec<_>>();
+  names.sort();
+  names.dedup();
+  let combinations = if names.len() <= MAX_FEATURE_POWERSET {
+    (0..1_usize << names.len())
+      .map(|bits| {
+        names
+          .iter()
+          .enumerate()
+          .filter(|(i, _)| bits & (1 << i) != 0)
+          .map(|(_, name)| name.clone())
+          .collect::<Vec<_>>()
+      })
+      .collect::<Vec<_>>()
+  } else {
+    let mut v = vec![Vec::new()];
+    v.extend(names.iter().map(|name| vec![name.clone()]));
+    v.push(names.clone());
+    v
+  };
+  let mut v = vec![FeatureSelection::default()];
+  v.extend(combinations.into_iter().map(|features| FeatureSelection {
+    features,
+    all_features: false,
+    no_default_features: true,
+  }));
+  v
+}
+
 #[allow(dead_code)]
 /// プロジェクトのメタデータを取得する
 pub(crate) fn manifest_to_metadata(path: &Path) -> anyhow::Result<Metadata> {
@@ -50,3 +209,80 @@ pub(crate) fn manifest_to_metadata(path: &Path) -> anyhow::Result<Metadata> {
     .with_context(|| format!("Faild to get metadata for {}", path.display()))?;
   Ok(metadata)
 }
+
+#[test]
+fn check_feature_selection() {
+  let manifest = get_manifest_data_from_contents(
+    r#"
+[package]
+name = "a"
+version = "0.1.0"
+
+[dependencies]
+serde = { version = "1", optional = true }
+rayon = { version = "1", optional = true }
+
+[features]
+default = ["std"]
+std = ["alloc"]
+alloc = []
+derive = ["dep:rayon", "serde/derive"]
+"#,
+  )
+  .unwrap();
+  let selection = FeatureSelection::default();
+  assert_eq!(
+    vec!["alloc", "default", "std"],
+    selection.resolve(&manifest)
+  );
+  let selection = FeatureSelection {
+    features: vec!["derive".to_string()],
+    all_features: false,
+    no_default_features: true,
+  };
+  assert_eq!(vec!["derive", "serde"], selection.resolve(&manifest));
+  assert!(selection.check_defined(&manifest, &manifest).is_ok());
+  let selection = FeatureSelection {
+    features: vec!["bogus".to_string(), "serde/derive".to_string()],
+    all_features: false,
+    no_default_features: false,
+  };
+  assert!(selection.check_defined(&manifest, &manifest).is_err());
+  assert_eq!(
+    vec!["alloc", "default", "derive", "serde", "std"],
+    feature_table(&manifest).into_keys().collect::<Vec<_>>()
+  );
+}
+
+#[test]
+fn check_feature_matrix() {
+  let manifest_with = |features: &[&str]| {
+    let mut contents = "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[features]\n".to_string();
+    contents.push_str("default = [\"a\"]\n");
+    for name in features.iter() {
+      contents.push_str(&format!("{name} = []\n"));
+    }
+    get_manifest_data_from_contents(&contents).unwrap()
+  };
+  let old = manifest_with(&["a"]);
+  let new = manifest_with(&["a", "b"]);
+  let shown = feature_matrix(&old, &new)
+    .iter()
+    .map(FeatureSelection::show)
+    .collect::<Vec<_>>();
+  assert_eq!(vec!["default", "no features", "a", "b", "a, b"], shown);
+
+  // featureが多い場合も、既定のfeatureでの比較は含める
+  let names = (0..10).map(|i| format!("f{i}")).collect::<Vec<_>>();
+  let many = manifest_with(&names.iter().map(String::as_str).collect::<Vec<_>>());
+  let matrix = feature_matrix(&many, &many);
+  assert_eq!(13, matrix.len());
+  let shown = matrix
+    .iter()
+    .map(FeatureSelection::show)
+    .collect::<Vec<_>>();
+  assert_eq!("default", shown[0]);
+  assert_eq!("no features", shown[1]);
+  assert_eq!("f0", shown[2]);
+  assert_eq!(names.join(", "), shown[12]);
+}
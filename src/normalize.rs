@@ -0,0 +1,538 @@
+use crate::types::{FnData, ItemTypeData, TraitItemData};
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
+use syn::{
+  FnArg, GenericParam, Generics, Ident, ImplItem, ItemImpl, Lifetime, Receiver, ReturnType, Type,
+  TypeBareFn, TypePath, TypeReference,
+};
+
+/// 書き方が違うだけで意味が同じシグネチャを、同じ形に揃える
+///
+/// `-> ()`を戻り値の省略に、省略規則で書かなくてよいライフタイムを省略した形にする
+pub(crate) fn normalize_types(data: &mut ItemTypeData) {
+  match data {
+    ItemTypeData::Fn(fn_data) => normalize_fn_data(fn_data),
+    ItemTypeData::Trait(trait_data) => {
+      for item in trait_data.items.iter_mut() {
+        if let TraitItemData::Fn(f) = item {
+          normalize_fn_data(&mut f.fn_data);
+        }
+      }
+    }
+    _ => (),
+  }
+}
+
+fn normalize_fn_data(fn_data: &mut FnData) {
+  if let ReturnType::Type(_, ty) = &fn_data.return_type {
+    if matches!(&**ty, Type::Tuple(tuple) if tuple.elems.is_empty()) {
+      fn_data.return_type = ReturnType::Default;
+    }
+  }
+  elide_lifetimes(fn_data);
+}
+
+/// 型の中に現れるライフタイムを数える
+#[derive(Default)]
+struct LifetimeUsageVisitor {
+  /// 書かれているライフタイム
+  named: Vec<String>,
+  /// ライフタイムを省略した参照の数
+  elided: usize,
+  /// 関数ポインタや`Fn(..)`の中に書かれているライフタイム
+  in_fn: Vec<String>,
+  /// 関数ポインタや`Fn(..)`の中では省略規則が別になる
+  fn_depth: usize,
+}
+
+impl<'ast> Visit<'ast> for LifetimeUsageVisitor {
+  fn visit_lifetime(&mut self, node: &'ast Lifetime) {
+    if self.fn_depth == 0 {
+      self.named.push(node.to_string());
+    } else if node.ident != "_" {
+      self.named.push(node.to_string());
+      self.in_fn.push(node.to_string());
+    }
+  }
+
+  fn visit_receiver(&mut self, node: &'ast Receiver) {
+    // `&'a self`のライフタイムは`ty`にも含まれている
+    self.visit_type(&node.ty);
+  }
+
+  fn visit_type_reference(&mut self, node: &'ast TypeReference) {
+    if node.lifetime.is_none() && self.fn_depth == 0 {
+      self.elided += 1;
+    }
+    syn::visit::visit_type_reference(self, node);
+  }
+
+  fn visit_type_bare_fn(&mut self, node: &'ast TypeBareFn) {
+    self.fn_depth += 1;
+    syn::visit::visit_type_bare_fn(self, node);
+    self.fn_depth -= 1;
+  }
+
+  fn visit_parenthesized_generic_arguments(
+    &mut self,
+    node: &'ast syn::ParenthesizedGenericArguments,
+  ) {
+    self.fn_depth += 1;
+    syn::visit::visit_parenthesized_generic_arguments(self, node);
+    self.fn_depth -= 1;
+  }
+}
+
+/// 指定したライフタイムを省略した形に書き換える
+///
+/// 参照からは取り除き、それ以外の位置では`'_`にする
+struct ElideLifetimeVisitor<'a> {
+  names: &'a [String],
+}
+
+impl ElideLifetimeVisitor<'_> {
+  fn is_elided(&self, lifetime: &Lifetime) -> bool {
+    lifetime.ident == "_" || self.names.contains(&lifetime.to_string())
+  }
+}
+
+impl VisitMut for ElideLifetimeVisitor<'_> {
+  fn visit_lifetime_mut(&mut self, node: &mut Lifetime) {
+    if self.is_elided(node) {
+      *node = Lifetime::new("'_", node.apostrophe);
+    }
+  }
+
+  fn visit_type_reference_mut(&mut self, node: &mut TypeReference) {
+    if node.lifetime.as_ref().is_some_and(|l| self.is_elided(l)) {
+      node.lifetime = None;
+    }
+    syn::visit_mut::visit_type_reference_mut(self, node);
+  }
+
+  fn visit_receiver_mut(&mut self, node: &mut Receiver) {
+    if let Some((_, lifetime)) = &mut node.reference {
+      if lifetime.as_ref().is_some_and(|l| self.is_elided(l)) {
+        *lifetime = None;
+      }
+    }
+    self.visit_type_mut(&mut node.ty);
+  }
+}
+
+/// 省略規則で補われるものと同じライフタイムを省略する
+///
+/// `fn f<'a>(x: &'a str) -> &'a str`は`fn f(x: &str) -> &str`と同じになる
+fn elide_lifetimes(fn_data: &mut FnData) {
+  let mut inputs = LifetimeUsageVisitor::default();
+  for arg in fn_data.args.iter() {
+    inputs.visit_fn_arg(arg);
+  }
+  let mut outputs = LifetimeUsageVisitor::default();
+  outputs.visit_return_type(&fn_data.return_type);
+
+  // 境界を通して他と関係しているライフタイムは省略できない
+  let mut bounds = LifetimeUsageVisitor::default();
+  for param in fn_data.generics.params.iter() {
+    match param {
+      GenericParam::Lifetime(l) => {
+        for bound in l.bounds.iter() {
+          bounds.visit_lifetime(bound);
+        }
+      }
+      GenericParam::Type(t) => {
+        for bound in t.bounds.iter() {
+          bounds.visit_type_param_bound(bound);
+        }
+      }
+      GenericParam::Const(_) => (),
+    }
+  }
+  if let Some(where_clause) = &fn_data.generics.where_clause {
+    bounds.visit_where_clause(where_clause);
+  }
+
+  // 参照で受け取る`self`があれば、戻り値の省略されたライフタイムはそれになる
+  let self_lifetime = fn_data.args.iter().find_map(|arg| match arg {
+    FnArg::Receiver(receiver) => match &*receiver.ty {
+      Type::Reference(reference) => Some(reference.lifetime.as_ref().map(|l| l.to_string())),
+      _ => None,
+    },
+    FnArg::Typed(_) => None,
+  });
+  let input_count = inputs.named.len() + inputs.elided;
+
+  let mut names = Vec::new();
+  for param in fn_data.generics.params.iter() {
+    let GenericParam::Lifetime(l) = param else {
+      continue;
+    };
+    let name = l.lifetime.to_string();
+    // 関数ポインタの中で省略すると、別のライフタイムになってしまう
+    if !l.bounds.is_empty()
+      || bounds.named.contains(&name)
+      || inputs.in_fn.contains(&name)
+      || outputs.in_fn.contains(&name)
+    {
+      continue;
+    }
+    let input_uses = inputs.named.iter().filter(|n| **n == name).count();
+    let output_uses = outputs.named.iter().filter(|n| **n == name).count();
+    let elidable = input_uses == 1
+      && (output_uses == 0
+        || match &self_lifetime {
+          Some(self_lifetime) => self_lifetime.as_ref() == Some(&name),
+          None => input_count == 1,
+        });
+    if elidable {
+      names.push(name);
+    }
+  }
+
+  let mut visitor = ElideLifetimeVisitor { names: &names };
+  for arg in fn_data.args.iter_mut() {
+    visitor.visit_fn_arg_mut(arg);
+  }
+  visitor.visit_return_type_mut(&mut fn_data.return_type);
+  fn_data.generics.params = fn_data
+    .generics
+    .params
+    .clone()
+    .into_iter()
+    .filter(|p| !matches!(p, GenericParam::Lifetime(l) if names.contains(&l.lifetime.to_string())))
+    .collect();
+  if fn_data.generics.params.is_empty() {
+    fn_data.generics.lt_token = None;
+    fn_data.generics.gt_token = None;
+  }
+}
+
+#[test]
+fn check_elide_lifetimes() {
+  let elided = |item: syn::ItemFn| {
+    let mut fn_data = crate::types::fn_data_from_sig(&[], &item.sig);
+    normalize_fn_data(&mut fn_data);
+    crate::types::show_tokens(&fn_data.generics)
+      + &fn_data
+        .args
+        .iter()
+        .map(crate::types::show_tokens)
+        .collect::<Vec<_>>()
+        .join(",")
+      + " -> "
+      + &crate::types::show_tokens(&fn_data.return_type)
+  };
+  assert_eq!(
+    elided(syn::parse_quote!(
+      fn f(x: &str) -> &str {}
+    )),
+    elided(syn::parse_quote!(
+      fn f<'a>(x: &'a str) -> &'a str {}
+    ))
+  );
+  assert_eq!(
+    elided(syn::parse_quote!(
+      fn f(&self, x: &str) -> &str {}
+    )),
+    elided(syn::parse_quote!(
+      fn f<'a, 'b>(&'a self, x: &'b str) -> &'a str {}
+    ))
+  );
+  assert_eq!(
+    elided(syn::parse_quote!(
+      fn f() {}
+    )),
+    elided(syn::parse_quote!(
+      fn f() -> () {}
+    ))
+  );
+  // 戻り値が`self`以外の引数を借りている場合は省略できない
+  assert_ne!(
+    elided(syn::parse_quote!(
+      fn f(&self, x: &str) -> &str {}
+    )),
+    elided(syn::parse_quote!(
+      fn f<'a>(&self, x: &'a str) -> &'a str {}
+    ))
+  );
+}
+
+/// 型の中の`Self`を実際の型に置き換える
+struct SelfTypeReplaceVisitor<'a> {
+  self_ty: &'a Type,
+}
+
+impl VisitMut for SelfTypeReplaceVisitor<'_> {
+  fn visit_type_mut(&mut self, node: &mut Type) {
+    if let Type::Path(TypePath { qself: None, path }) = node {
+      if path.is_ident("Self") {
+        *node = self.self_ty.clone();
+        return;
+      }
+    }
+    syn::visit_mut::visit_type_mut(self, node);
+  }
+
+  fn visit_receiver_mut(&mut self, _node: &mut Receiver) {
+    // `self`の型は呼び出し側から見て`Self`のままでよい
+  }
+}
+
+/// 固有実装のアイテムに現れる`Self`を実装対象の型に置き換える
+pub(crate) fn replace_self_type(data: &mut ItemTypeData, self_ty: &Type) {
+  let mut visitor = SelfTypeReplaceVisitor { self_ty };
+  match data {
+    ItemTypeData::Const(d) => visitor.visit_type_mut(&mut d.ty),
+    ItemTypeData::Fn(d) => {
+      for arg in d.args.iter_mut() {
+        visitor.visit_fn_arg_mut(arg);
+      }
+      visitor.visit_return_type_mut(&mut d.return_type);
+      visitor.visit_generics_mut(&mut d.generics);
+    }
+    _ => (),
+  }
+}
+
+/// 新しい方のジェネリクスの引数の名前を、位置が対応する古い方の名前に置き換えるvisitorを作る
+///
+/// 置き換えるものが無いか、置き換え先の名前が他の引数と重なる場合は`None`を返す
+fn rename_visitor_like(
+  old_generics: &Generics,
+  new_generics: &Generics,
+) -> Option<RenameParamVisitor> {
+  let is_lifetime = |p: &&GenericParam| matches!(p, GenericParam::Lifetime(_));
+  let is_not_lifetime = |p: &&GenericParam| !matches!(p, GenericParam::Lifetime(_));
+  let mut visitor = RenameParamVisitor {
+    lifetimes: Vec::new(),
+    params: Vec::new(),
+  };
+  for filter in [is_lifetime, is_not_lifetime] {
+    for (old_param, new_param) in old_generics
+      .params
+      .iter()
+      .filter(filter)
+      .zip(new_generics.params.iter().filter(filter))
+    {
+      match (old_param, new_param) {
+        (GenericParam::Lifetime(o), GenericParam::Lifetime(n)) if o.lifetime != n.lifetime => {
+          visitor
+            .lifetimes
+            .push((n.lifetime.clone(), o.lifetime.clone()))
+        }
+        (GenericParam::Type(o), GenericParam::Type(n)) if o.ident != n.ident => {
+          visitor.params.push((n.ident.clone(), o.ident.clone()))
+        }
+        (GenericParam::Const(o), GenericParam::Const(n)) if o.ident != n.ident => {
+          visitor.params.push((n.ident.clone(), o.ident.clone()))
+        }
+        _ => (),
+      }
+    }
+  }
+  if visitor.lifetimes.is_empty() && visitor.params.is_empty() {
+    return None;
+  }
+  // 置き換え先の名前が、置き換えない他の引数と重なる場合はやめる
+  let collides = new_generics.params.iter().any(|p| match p {
+    GenericParam::Lifetime(l) => {
+      visitor.lifetimes.iter().any(|(_, to)| *to == l.lifetime)
+        && !visitor
+          .lifetimes
+          .iter()
+          .any(|(from, _)| *from == l.lifetime)
+    }
+    GenericParam::Type(syn::TypeParam { ident, .. })
+    | GenericParam::Const(syn::ConstParam { ident, .. }) => {
+      visitor.params.iter().any(|(_, to)| to == ident)
+        && !visitor.params.iter().any(|(from, _)| from == ident)
+    }
+  });
+  if collides {
+    return None;
+  }
+  Some(visitor)
+}
+
+/// 新しい方のジェネリクスの引数の名前を、位置が対応する古い方の名前に揃える
+///
+/// 名前を変えただけの`fn f<T>(x: T)`と`fn f<U>(x: U)`を同じものとして比較するため
+pub(crate) fn rename_params_like(old_generics: &Generics, new_fn: &mut FnData) {
+  let Some(mut visitor) = rename_visitor_like(old_generics, &new_fn.generics) else {
+    return;
+  };
+  visitor.visit_generics_mut(&mut new_fn.generics);
+  for arg in new_fn.args.iter_mut() {
+    visitor.visit_fn_arg_mut(arg);
+  }
+  visitor.visit_return_type_mut(&mut new_fn.return_type);
+}
+
+/// 型の定義について、新しい方のジェネリクスの引数の名前を古い方に揃える
+///
+/// 名前を変えただけの`struct S<T>(T)`と`struct S<U>(U)`のフィールドを同じものとして比較するため
+pub(crate) fn rename_item_params_like(old_data: &ItemTypeData, new_data: &mut ItemTypeData) {
+  let old_generics = match old_data {
+    ItemTypeData::Struct(d) => &d.generics,
+    ItemTypeData::Enum(d) => &d.generics,
+    ItemTypeData::Union(d) => &d.generics,
+    ItemTypeData::Type(d) => &d.generics,
+    ItemTypeData::Trait(d) => &d.generics,
+    _ => return,
+  };
+  match new_data {
+    ItemTypeData::Struct(d) => {
+      if let Some(mut visitor) = rename_visitor_like(old_generics, &d.generics) {
+        visitor.visit_generics_mut(&mut d.generics);
+        visitor.visit_fields_mut(&mut d.fields);
+      }
+    }
+    ItemTypeData::Enum(d) => {
+      if let Some(mut visitor) = rename_visitor_like(old_generics, &d.generics) {
+        visitor.visit_generics_mut(&mut d.generics);
+        for variant in d.variants.iter_mut() {
+          visitor.visit_variant_mut(variant);
+        }
+      }
+    }
+    ItemTypeData::Union(d) => {
+      if let Some(mut visitor) = rename_visitor_like(old_generics, &d.generics) {
+        visitor.visit_generics_mut(&mut d.generics);
+        visitor.visit_fields_mut(&mut d.fields);
+      }
+    }
+    ItemTypeData::Type(d) => {
+      if let Some(mut visitor) = rename_visitor_like(old_generics, &d.generics) {
+        visitor.visit_generics_mut(&mut d.generics);
+        visitor.visit_type_mut(&mut d.ty);
+      }
+    }
+    ItemTypeData::Trait(d) => {
+      if let Some(mut visitor) = rename_visitor_like(old_generics, &d.generics) {
+        visitor.visit_generics_mut(&mut d.generics);
+        for bound in d.supertraits.iter_mut() {
+          visitor.visit_type_param_bound_mut(bound);
+        }
+        for item in d.items.iter_mut() {
+          match item {
+            TraitItemData::Const(c) => {
+              visitor.visit_generics_mut(&mut c.generics);
+              visitor.visit_type_mut(&mut c.ty);
+            }
+            TraitItemData::Type(t) => {
+              visitor.visit_generics_mut(&mut t.generics);
+              for bound in t.bounds.iter_mut() {
+                visitor.visit_type_param_bound_mut(bound);
+              }
+            }
+            TraitItemData::Fn(f) => {
+              let fn_data = &mut f.fn_data;
+              visitor.visit_generics_mut(&mut fn_data.generics);
+              for arg in fn_data.args.iter_mut() {
+                visitor.visit_fn_arg_mut(arg);
+              }
+              visitor.visit_return_type_mut(&mut fn_data.return_type);
+            }
+          }
+        }
+      }
+    }
+    _ => (),
+  }
+}
+
+/// implのジェネリクスの引数の名前を、宣言された位置から決まる名前に置き換える
+///
+/// 名前を変えただけの`impl<T> Tr for W<T>`と`impl<U> Tr for W<U>`を同じものとして比較するため
+pub(crate) fn rename_impl_params_by_position(item_impl: &mut ItemImpl) {
+  let mut visitor = RenameParamVisitor {
+    lifetimes: Vec::new(),
+    params: Vec::new(),
+  };
+  let mut lifetime_count = 0;
+  let mut param_count = 0;
+  for param in item_impl.generics.params.iter() {
+    match param {
+      GenericParam::Lifetime(l) => {
+        let to = Lifetime::new(&format!("'__L{lifetime_count}"), l.lifetime.span());
+        visitor.lifetimes.push((l.lifetime.clone(), to));
+        lifetime_count += 1;
+      }
+      GenericParam::Type(syn::TypeParam { ident, .. })
+      | GenericParam::Const(syn::ConstParam { ident, .. }) => {
+        let to = Ident::new(&format!("__P{param_count}"), ident.span());
+        visitor.params.push((ident.clone(), to));
+        param_count += 1;
+      }
+    }
+  }
+  visitor.visit_generics_mut(&mut item_impl.generics);
+  if let Some((_, trait_path, _)) = &mut item_impl.trait_ {
+    visitor.visit_path_mut(trait_path);
+  }
+  visitor.visit_type_mut(&mut item_impl.self_ty);
+  // 関連アイテムのシグネチャも合わせる。本体は比較しないので触らない
+  for impl_item in item_impl.items.iter_mut() {
+    match impl_item {
+      ImplItem::Const(impl_const) => {
+        visitor.visit_generics_mut(&mut impl_const.generics);
+        visitor.visit_type_mut(&mut impl_const.ty);
+      }
+      ImplItem::Fn(impl_fn) => visitor.visit_signature_mut(&mut impl_fn.sig),
+      ImplItem::Type(impl_type) => {
+        visitor.visit_generics_mut(&mut impl_type.generics);
+        visitor.visit_type_mut(&mut impl_type.ty);
+      }
+      _ => (),
+    }
+  }
+}
+
+/// ジェネリクスの引数の名前を一度に置き換える
+struct RenameParamVisitor {
+  lifetimes: Vec<(Lifetime, Lifetime)>,
+  params: Vec<(Ident, Ident)>,
+}
+
+impl RenameParamVisitor {
+  fn rename_param(&self, ident: &mut Ident) {
+    if let Some((_, to)) = self.params.iter().find(|(from, _)| from == ident) {
+      *ident = to.clone();
+    }
+  }
+}
+
+impl VisitMut for RenameParamVisitor {
+  fn visit_lifetime_mut(&mut self, node: &mut Lifetime) {
+    if let Some((_, to)) = self.lifetimes.iter().find(|(from, _)| from == node) {
+      *node = to.clone();
+    }
+  }
+
+  fn visit_type_param_mut(&mut self, node: &mut syn::TypeParam) {
+    self.rename_param(&mut node.ident);
+    syn::visit_mut::visit_type_param_mut(self, node);
+  }
+
+  fn visit_const_param_mut(&mut self, node: &mut syn::ConstParam) {
+    self.rename_param(&mut node.ident);
+    syn::visit_mut::visit_const_param_mut(self, node);
+  }
+
+  fn visit_pat_type_mut(&mut self, node: &mut syn::PatType) {
+    // 引数の名前はそのままにする
+    self.visit_type_mut(&mut node.ty);
+  }
+
+  fn visit_path_mut(&mut self, node: &mut syn::Path) {
+    // 型引数を指しうるのは先頭のセグメントだけ
+    if node.leading_colon.is_none() {
+      if let Some(first) = node.segments.first_mut() {
+        self.rename_param(&mut first.ident);
+      }
+    }
+    for segment in node.segments.iter_mut() {
+      self.visit_path_arguments_mut(&mut segment.arguments);
+    }
+  }
+}
@@ -0,0 +1,59 @@
+use anyhow::anyhow;
+use git2::{ObjectType, Repository, Tree};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// モジュールのファイルを読み込む元
+///
+/// 作業ディレクトリとgitのツリーで、同じ規則でモジュールを探すために使う
+pub(crate) trait ModuleSource {
+  /// ソースのルート（`lib.rs`のあるディレクトリ）からの相対パスでファイルを読む
+  ///
+  /// ファイルが無ければ`None`を返す
+  fn read_file(&self, path: &Path) -> anyhow::Result<Option<String>>;
+}
+
+/// 作業ディレクトリ
+pub(crate) struct DirSource {
+  pub root: PathBuf,
+}
+
+impl ModuleSource for DirSource {
+  fn read_file(&self, path: &Path) -> anyhow::Result<Option<String>> {
+    let path = self.root.join(path);
+    if !path.is_file() {
+      return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+  }
+}
+
+/// gitのコミットのツリー
+pub(crate) struct GitTreeSource<'a> {
+  pub repo: &'a Repository,
+  pub tree: &'a Tree<'a>,
+  /// リポジトリのルートからソースのルートまでのパス
+  pub root: Option<PathBuf>,
+}
+
+impl ModuleSource for GitTreeSource<'_> {
+  fn read_file(&self, path: &Path) -> anyhow::Result<Option<String>> {
+    let path = match &self.root {
+      Some(root) => root.join(path),
+      None => path.to_path_buf(),
+    };
+    let tree_entry = match self.tree.get_path(&path) {
+      Ok(tree_entry) => tree_entry,
+      Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+      Err(e) => return Err(e.into()),
+    };
+    if tree_entry.kind() != Some(ObjectType::Blob) {
+      return Ok(None);
+    }
+    let obj = tree_entry.to_object(self.repo)?;
+    let blob = obj
+      .as_blob()
+      .ok_or_else(|| anyhow!("Failed get blob from {}", path.display()))?;
+    Ok(Some(String::from_utf8(blob.content().to_vec())?))
+  }
+}
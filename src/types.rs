@@ -1,6 +1,16 @@
+use crate::attrs;
+use crate::exports::{self, PublicSurface};
+use crate::file::{self, ModuleInfo};
+use crate::generics;
+use crate::normalize;
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
 use syn::{
-  Attribute, Fields, FnArg, Generics, Ident, Item, ReturnType, StaticMutability, Type, Variant,
-  Visibility,
+  Attribute, Fields, FnArg, GenericParam, Generics, Ident, ImplItem, Item, Pat, ReturnType,
+  Signature, StaticMutability, TraitItem, Type, TypeImplTrait, TypeParamBound, TypePath,
+  TypeTraitObject, Variant, Visibility, WherePredicate,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -58,11 +68,87 @@ pub(crate) struct FnData {
   pub is_const: bool,
   pub is_async: bool,
   pub is_unsafe: bool,
+  /// 呼び出し規約（`extern`が無ければ`"Rust"`）
+  pub abi: String,
+  pub is_variadic: bool,
   pub generics: Generics,
   pub args: Vec<FnArg>,
   pub return_type: ReturnType,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TraitConstData {
+  pub attrs: Vec<Attribute>,
+  pub ident: Ident,
+  pub generics: Generics,
+  pub ty: Type,
+  pub has_default: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TraitTypeData {
+  pub attrs: Vec<Attribute>,
+  pub ident: Ident,
+  pub generics: Generics,
+  pub bounds: Punctuated<TypeParamBound, syn::Token![+]>,
+  pub has_default: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TraitFnData {
+  pub fn_data: FnData,
+  pub has_default: bool,
+}
+
+/// traitが持つ関連アイテム
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TraitItemData {
+  Const(TraitConstData),
+  Type(TraitTypeData),
+  Fn(TraitFnData),
+}
+
+impl TraitItemData {
+  fn ident(&self) -> &Ident {
+    match self {
+      Self::Const(d) => &d.ident,
+      Self::Type(d) => &d.ident,
+      Self::Fn(d) => &d.fn_data.ident,
+    }
+  }
+
+  fn has_default(&self) -> bool {
+    match self {
+      Self::Const(d) => d.has_default,
+      Self::Type(d) => d.has_default,
+      Self::Fn(d) => d.has_default,
+    }
+  }
+
+  fn show_name(&self) -> String {
+    match self {
+      Self::Const(d) => format!("const {}", d.ident),
+      Self::Type(d) => format!("type {}", d.ident),
+      Self::Fn(d) => format!("fn {}", d.fn_data.ident),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TraitData {
+  pub attrs: Vec<Attribute>,
+  pub ident: Ident,
+  pub is_unsafe: bool,
+  pub is_auto: bool,
+  pub generics: Generics,
+  pub supertraits: Punctuated<TypeParamBound, syn::Token![+]>,
+  pub items: Vec<TraitItemData>,
+  /// 外部から実装できないtraitかどうか
+  pub is_sealed: bool,
+  /// 公開シグネチャの中で`dyn Trait`として使われている場所
+  pub dyn_usage: Vec<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct MacroData {
   pub attrs: Vec<Attribute>,
@@ -78,10 +164,45 @@ pub(crate) enum ItemTypeData {
   Struct(StructData),
   Enum(EnumData),
   Fn(FnData),
+  Trait(TraitData),
   Macro(MacroData),
 }
 
 impl ItemTypeData {
+  pub(crate) fn ident(&self) -> &Ident {
+    match self {
+      Self::Const(d) => &d.ident,
+      Self::Static(d) => &d.ident,
+      Self::Union(d) => &d.ident,
+      Self::Type(d) => &d.ident,
+      Self::Struct(d) => &d.ident,
+      Self::Enum(d) => &d.ident,
+      Self::Fn(d) => &d.ident,
+      Self::Trait(d) => &d.ident,
+      Self::Macro(d) => &d.ident,
+    }
+  }
+
+  /// 再エクスポートで付けられた名前に置き換える
+  pub(crate) fn set_ident(&mut self, ident: Ident) {
+    match self {
+      Self::Const(d) => d.ident = ident,
+      Self::Static(d) => d.ident = ident,
+      Self::Union(d) => d.ident = ident,
+      Self::Type(d) => d.ident = ident,
+      Self::Struct(d) => d.ident = ident,
+      Self::Enum(d) => d.ident = ident,
+      Self::Fn(d) => d.ident = ident,
+      Self::Trait(d) => d.ident = ident,
+      Self::Macro(d) => d.ident = ident,
+    }
+  }
+
+  /// 同じ種類のアイテムかどうか
+  fn is_same_kind(&self, other: &Self) -> bool {
+    std::mem::discriminant(self) == std::mem::discriminant(other)
+  }
+
   pub(crate) fn show_name(&self) -> String {
     match self {
       Self::Const(d) => format!("const {}", d.ident),
@@ -91,11 +212,72 @@ impl ItemTypeData {
       Self::Struct(d) => format!("struct {}", d.ident),
       Self::Enum(d) => format!("enum {}", d.ident),
       Self::Fn(d) => format!("fn {}", d.ident),
+      Self::Trait(d) => format!("trait {}", d.ident),
       Self::Macro(d) => format!("macro {}", d.ident),
     }
   }
 }
 
+/// 関数のシグネチャから情報を抽出する
+pub(crate) fn fn_data_from_sig(attrs: &[Attribute], sig: &Signature) -> FnData {
+  let is_const = sig.constness.is_some();
+  let is_async = sig.asyncness.is_some();
+  let is_unsafe = sig.unsafety.is_some();
+  let abi = match &sig.abi {
+    Some(abi) => abi
+      .name
+      .as_ref()
+      .map(|name| name.value())
+      .unwrap_or_else(|| "C".to_string()),
+    None => "Rust".to_string(),
+  };
+  let mut args = Vec::new();
+  for arg in sig.inputs.iter() {
+    args.push(arg.clone())
+  }
+  FnData {
+    attrs: attrs.to_vec(),
+    ident: sig.ident.clone(),
+    is_const,
+    is_async,
+    is_unsafe,
+    abi,
+    is_variadic: sig.variadic.is_some(),
+    generics: sig.generics.clone(),
+    args,
+    return_type: sig.output.clone(),
+  }
+}
+
+/// traitの関連アイテムを抽出する
+fn extract_trait_items(items: &[TraitItem]) -> Vec<TraitItemData> {
+  let mut v = Vec::new();
+  for item in items.iter() {
+    match item {
+      TraitItem::Const(item_const) => v.push(TraitItemData::Const(TraitConstData {
+        attrs: item_const.attrs.clone(),
+        ident: item_const.ident.clone(),
+        generics: item_const.generics.clone(),
+        ty: item_const.ty.clone(),
+        has_default: item_const.default.is_some(),
+      })),
+      TraitItem::Type(item_type) => v.push(TraitItemData::Type(TraitTypeData {
+        attrs: item_type.attrs.clone(),
+        ident: item_type.ident.clone(),
+        generics: item_type.generics.clone(),
+        bounds: item_type.bounds.clone(),
+        has_default: item_type.default.is_some(),
+      })),
+      TraitItem::Fn(item_fn) => v.push(TraitItemData::Fn(TraitFnData {
+        fn_data: fn_data_from_sig(&item_fn.attrs, &item_fn.sig),
+        has_default: item_fn.default.is_some(),
+      })),
+      _ => (),
+    }
+  }
+  v
+}
+
 /// moduleの中から型情報を抽出する関数
 pub(crate) fn extract_types(items: &[Item]) -> Vec<ItemTypeData> {
   let mut v = Vec::new();
@@ -167,23 +349,24 @@ pub(crate) fn extract_types(items: &[Item]) -> Vec<ItemTypeData> {
       }
       Item::Fn(item_fn) => {
         if let Visibility::Public(_) = item_fn.vis {
-          let sig = &item_fn.sig;
-          let is_const = sig.constness.is_some();
-          let is_async = sig.asyncness.is_some();
-          let is_unsafe = sig.unsafety.is_some();
-          let mut args = Vec::new();
-          for arg in sig.inputs.iter() {
-            args.push(arg.clone())
-          }
-          v.push(ItemTypeData::Fn(FnData {
-            attrs: item_fn.attrs.clone(),
-            ident: sig.ident.clone(),
-            is_const,
-            is_async,
-            is_unsafe,
-            generics: sig.generics.clone(),
-            args,
-            return_type: sig.output.clone(),
+          v.push(ItemTypeData::Fn(fn_data_from_sig(
+            &item_fn.attrs,
+            &item_fn.sig,
+          )))
+        }
+      }
+      Item::Trait(item_trait) => {
+        if let Visibility::Public(_) = item_trait.vis {
+          v.push(ItemTypeData::Trait(TraitData {
+            attrs: item_trait.attrs.clone(),
+            ident: item_trait.ident.clone(),
+            is_unsafe: item_trait.unsafety.is_some(),
+            is_auto: item_trait.auto_token.is_some(),
+            generics: item_trait.generics.clone(),
+            supertraits: item_trait.supertraits.clone(),
+            items: extract_trait_items(&item_trait.items),
+            is_sealed: false,
+            dyn_usage: Vec::new(),
           }))
         }
       }
@@ -201,195 +384,2992 @@ pub(crate) fn extract_types(items: &[Item]) -> Vec<ItemTypeData> {
   v
 }
 
+/// supertraitの中に、クレートの外から名前を指定できないtraitがあるか
+fn has_sealing_supertrait(
+  supertraits: &Punctuated<TypeParamBound, syn::Token![+]>,
+  module_info: &ModuleInfo,
+  module_info_list: &[ModuleInfo],
+  surface: &PublicSurface,
+  depth: usize,
+) -> bool {
+  if depth > 16 {
+    return false;
+  }
+  supertraits.iter().any(|bound| {
+    let TypeParamBound::Trait(trait_bound) = bound else {
+      return false;
+    };
+    let segments = trait_bound
+      .path
+      .segments
+      .iter()
+      .map(|s| s.ident.to_string())
+      .collect::<Vec<_>>();
+    let Some(path) = file::resolve_local_path(module_info, &segments) else {
+      return false;
+    };
+    let Some(def) = surface.def_of(&path) else {
+      return true;
+    };
+    // 公開されたsupertraitがsealedならこちらもsealedになる
+    let (name, mod_path) = def.split_last().unwrap();
+    exports::find_module(module_info_list, mod_path).is_some_and(|m| {
+      m.items.iter().any(|item| match item {
+        Item::Trait(t) if t.ident == name => {
+          has_sealing_supertrait(&t.supertraits, m, module_info_list, surface, depth + 1)
+        }
+        _ => false,
+      })
+    })
+  })
+}
+
+/// sealed traitに印をつける
+///
+/// supertraitが公開されたパスから到達できなければ、クレートの外からは実装できない
+pub(crate) fn mark_sealed_traits(
+  data_list: &mut [ItemTypeData],
+  module_info: &ModuleInfo,
+  module_info_list: &[ModuleInfo],
+  surface: &PublicSurface,
+) {
+  for data in data_list.iter_mut() {
+    if let ItemTypeData::Trait(trait_data) = data {
+      trait_data.is_sealed = has_sealing_supertrait(
+        &trait_data.supertraits,
+        module_info,
+        module_info_list,
+        surface,
+        0,
+      );
+    }
+  }
+}
+
+#[test]
+fn check_mark_sealed_traits() {
+  let root: syn::File = syn::parse_quote! {
+    mod private {
+      pub trait Sealed {}
+    }
+    pub trait Open {}
+    pub trait Foo: private::Sealed {}
+    pub trait Bar: Open + Clone {}
+    pub trait Baz: Open + Foo {}
+  };
+  let private: syn::File = syn::parse_quote! {
+    pub trait Sealed {}
+  };
+  let list = vec![
+    ModuleInfo::new(vec![syn::parse_quote!(private)], private.items),
+    ModuleInfo::new(Vec::new(), root.items),
+  ];
+  let surface = exports::public_surface(&list);
+  let mut data_list = extract_types(&list[1].items);
+  mark_sealed_traits(&mut data_list, &list[1], &list, &surface);
+  let sealed = data_list
+    .iter()
+    .filter_map(|data| match data {
+      ItemTypeData::Trait(t) => Some((t.ident.to_string(), t.is_sealed)),
+      _ => None,
+    })
+    .collect::<Vec<_>>();
+  // 公開されたsealed traitをsupertraitに持つ`Baz`もsealedになる
+  assert_eq!(
+    vec![
+      ("Open".to_string(), false),
+      ("Foo".to_string(), true),
+      ("Bar".to_string(), false),
+      ("Baz".to_string(), true),
+    ],
+    sealed
+  );
+}
+
+/// 型が指しているクレート内のアイテムの絶対パス
+pub(crate) fn resolve_type_path(module_info: &ModuleInfo, ty: &Type) -> Option<Vec<String>> {
+  match ty {
+    Type::Path(type_path) if type_path.qself.is_none() => {
+      let segments = type_path
+        .path
+        .segments
+        .iter()
+        .map(|s| s.ident.to_string())
+        .collect::<Vec<_>>();
+      file::resolve_local_path(module_info, &segments)
+    }
+    Type::Group(g) => resolve_type_path(module_info, &g.elem),
+    Type::Paren(p) => resolve_type_path(module_info, &p.elem),
+    _ => None,
+  }
+}
+
+/// 固有実装の中で公開されている関連アイテム
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) enum ResultDetermineCompatibility {
-  Ok,
-  Uncompatible(ItemTypeData),
-  NotFound,
+pub(crate) struct InherentImplData {
+  /// 実装対象の型のクレート内での絶対パス
+  pub self_path: Vec<String>,
+  pub items: Vec<ItemTypeData>,
 }
 
-fn fileds_eq(old_fields: &Fields, new_fields: &Fields) -> bool {
-  match (old_fields, new_fields) {
-    (Fields::Unit, Fields::Unit) => true,
-    (Fields::Unnamed(old_unnamed), Fields::Unnamed(new_unnamed)) => {
-      let old_len = old_unnamed.unnamed.len();
-      let new_len = new_unnamed.unnamed.len();
-      if old_len == new_len {
-        let f_zip = old_unnamed.unnamed.iter().zip(new_unnamed.unnamed.iter());
-        for (old_f, new_f) in f_zip {
-          if old_f.ty != new_f.ty {
-            return false;
+/// クレート全体から固有実装を集め、実装対象の型ごとにまとめる
+pub(crate) fn extract_inherent_impls(
+  module_info_list: &[ModuleInfo],
+  aliases: &[TypeAliasData],
+  surface: &PublicSurface,
+) -> Vec<InherentImplData> {
+  let mut v: Vec<InherentImplData> = Vec::new();
+  for module_info in module_info_list.iter() {
+    for item in module_info.items.iter() {
+      let Item::Impl(item_impl) = item else {
+        continue;
+      };
+      if item_impl.trait_.is_some() {
+        continue;
+      }
+      let Some(self_path) = resolve_type_path(module_info, &item_impl.self_ty) else {
+        continue;
+      };
+      // 名前を変えただけの`impl<T> W<T>`と`impl<U> W<U>`のメソッドを同じものとして比較する
+      let mut item_impl = item_impl.clone();
+      normalize::rename_impl_params_by_position(&mut item_impl);
+      let mut items = Vec::new();
+      for impl_item in item_impl.items.iter() {
+        match impl_item {
+          ImplItem::Const(impl_const) => {
+            if let Visibility::Public(_) = impl_const.vis {
+              items.push(ItemTypeData::Const(ConstData {
+                attrs: impl_const.attrs.clone(),
+                ident: impl_const.ident.clone(),
+                generics: impl_const.generics.clone(),
+                ty: Box::new(impl_const.ty.clone()),
+              }))
+            }
           }
+          ImplItem::Fn(impl_fn) => {
+            if let Visibility::Public(_) = impl_fn.vis {
+              items.push(ItemTypeData::Fn(fn_data_from_sig(
+                &impl_fn.attrs,
+                &impl_fn.sig,
+              )))
+            }
+          }
+          _ => (),
         }
-        true
+      }
+      let params = param_names(&item_impl.generics);
+      let mut self_ty = (*item_impl.self_ty).clone();
+      CanonicalPathVisitor {
+        module_info,
+        params: params.clone(),
+      }
+      .visit_type_mut(&mut self_ty);
+      for data in items.iter_mut() {
+        canonicalize_types_in(data, module_info, params.clone());
+        normalize::replace_self_type(data, &self_ty);
+        expand_type_aliases(data, aliases);
+        publicize_paths(data, surface);
+        normalize::normalize_types(data);
+      }
+      if let Some(data) = v.iter_mut().find(|d| d.self_path == self_path) {
+        data.items.extend(items);
       } else {
-        false
+        v.push(InherentImplData { self_path, items });
       }
     }
-    (Fields::Named(old_named), Fields::Named(new_named)) => {
-      for old_f in old_named.named.iter() {
-        if let Visibility::Public(_) = old_f.vis {
-          if let Some(old_ident) = &old_f.ident {
-            if let Some(new_f) = new_named
-              .named
-              .iter()
-              .find(|f| f.ident.clone().map(|i| i.to_string()) == Some(old_ident.to_string()))
-            {
-              if old_f.ty != new_f.ty {
-                return false;
-              }
-            } else {
-              return false;
+  }
+  v
+}
+
+#[test]
+fn check_extract_inherent_impls() {
+  let impls = |root: syn::File, a: syn::File| {
+    let list = vec![
+      ModuleInfo::new(vec![syn::parse_quote!(a)], a.items),
+      ModuleInfo::new(Vec::new(), root.items),
+    ];
+    let surface = exports::public_surface(&list);
+    extract_inherent_impls(&list, &[], &surface)
+  };
+  let old_impls = impls(
+    syn::parse_quote! {
+      pub mod a;
+      pub struct S<T>(T);
+      impl<T: Clone> S<T> {
+        pub fn get(&self) -> T { todo!() }
+        pub fn new() -> Self { todo!() }
+        pub fn len(&self) -> u8 { 0 }
+        pub fn removed(&self) {}
+        fn private(&self) {}
+      }
+    },
+    syn::parse_quote! {
+      impl<T> super::S<T> {
+        pub fn from_a(&self) {}
+      }
+    },
+  );
+  let new_impls = impls(
+    syn::parse_quote! {
+      pub mod a;
+      pub struct S<T>(T);
+      impl<U: Clone> S<U> {
+        pub fn get(&self) -> U { todo!() }
+        pub fn new() -> S<U> { todo!() }
+        pub fn len(&self) -> u16 { 0 }
+      }
+    },
+    syn::parse_quote! {
+      impl<T> crate::S<T> {
+        pub fn from_a(&self) {}
+      }
+    },
+  );
+  // 別のモジュールにある固有実装も同じ型のものとしてまとめる
+  assert_eq!(1, old_impls.len());
+  assert_eq!(vec!["S".to_string()], old_impls[0].self_path);
+  let results = old_impls[0]
+    .items
+    .iter()
+    .map(|old_data| {
+      let result = match determine_compatibility(old_data, &new_impls[0].items) {
+        ResultDetermineCompatibility::Ok => "ok",
+        ResultDetermineCompatibility::Compatible(_) => "compatible",
+        ResultDetermineCompatibility::Uncompatible(_, _) => "uncompatible",
+        ResultDetermineCompatibility::NotFound => "not found",
+      };
+      (old_data.ident().to_string(), result)
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      ("from_a".to_string(), "ok"),
+      ("get".to_string(), "ok"),
+      ("new".to_string(), "ok"),
+      ("len".to_string(), "uncompatible"),
+      ("removed".to_string(), "not found"),
+    ],
+    results
+  );
+}
+
+/// 型の中に現れるクレート内のアイテムのパスを集める
+struct LocalPathVisitor<'a> {
+  module_info: &'a ModuleInfo,
+  paths: Vec<Vec<String>>,
+}
+
+impl<'ast> Visit<'ast> for LocalPathVisitor<'_> {
+  fn visit_type_path(&mut self, node: &'ast TypePath) {
+    if let Some(path) = resolve_type_path(self.module_info, &Type::Path(node.clone())) {
+      self.paths.push(path);
+    }
+    syn::visit::visit_type_path(self, node);
+  }
+}
+
+/// 組み込みの型の名前
+const PRIMITIVE_TYPES: [&str; 17] = [
+  "bool", "char", "str", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64",
+  "u128", "usize", "f32", "f64",
+];
+
+/// パスを正規化したパスに置き換える
+///
+/// `params`はスコープにあるジェネリクスの引数の名前。最後のセグメントの型引数はそのまま引き継ぐ
+fn canonicalize_path(module_info: &ModuleInfo, params: &[String], path: &mut syn::Path) {
+  let segments = path
+    .segments
+    .iter()
+    .map(|s| s.ident.to_string())
+    .collect::<Vec<_>>();
+  let mut canonical = file::canonical_path(module_info, &segments);
+  // 解決できない名前は、クレートの外を指すglobで導入されたものとみなす
+  if let [name] = segments.as_slice() {
+    if canonical == segments
+      && name != "Self"
+      && !params.contains(name)
+      && !PRIMITIVE_TYPES.contains(&name.as_str())
+    {
+      if let Some(mut glob) = file::external_glob(module_info) {
+        glob.push(name.clone());
+        canonical = glob;
+      }
+    }
+  }
+  if canonical != segments {
+    replace_path_segments(path, &canonical);
+  }
+}
+
+/// `Ident::to_string()`で得た名前から識別子を作り直す
+///
+/// `r#async`のような生識別子は`r#`を外して作る。位置の情報は`span_of`から取る
+pub(crate) fn ident_from_str(name: &str, span_of: &Ident) -> Ident {
+  match name.strip_prefix("r#") {
+    Some(raw) => Ident::new_raw(raw, span_of.span()),
+    None => Ident::new(name, span_of.span()),
+  }
+}
+
+/// パスのセグメントを置き換える
+///
+/// 最後のセグメントの型引数はそのまま引き継ぐ
+fn replace_path_segments(path: &mut syn::Path, segments: &[String]) {
+  let Some(last) = path.segments.last() else {
+    return;
+  };
+  let arguments = last.arguments.clone();
+  let span_of = last.ident.clone();
+  path.leading_colon = None;
+  path.segments = segments
+    .iter()
+    .map(|s| syn::PathSegment::from(ident_from_str(s, &span_of)))
+    .collect();
+  if let Some(last) = path.segments.last_mut() {
+    last.arguments = arguments;
+  }
+}
+
+/// 型やtraitのパスを、モジュールの`use`を展開した完全なパスに置き換える
+struct CanonicalPathVisitor<'a> {
+  module_info: &'a ModuleInfo,
+  /// スコープにあるジェネリクスの引数の名前
+  params: Vec<String>,
+}
+
+impl VisitMut for CanonicalPathVisitor<'_> {
+  fn visit_type_path_mut(&mut self, node: &mut TypePath) {
+    if node.qself.is_none() {
+      canonicalize_path(self.module_info, &self.params, &mut node.path);
+    }
+    syn::visit_mut::visit_type_path_mut(self, node);
+  }
+
+  fn visit_trait_bound_mut(&mut self, node: &mut syn::TraitBound) {
+    canonicalize_path(self.module_info, &self.params, &mut node.path);
+    syn::visit_mut::visit_trait_bound_mut(self, node);
+  }
+}
+
+/// ジェネリクスの引数の名前を集める
+#[derive(Default)]
+struct ParamNameVisitor {
+  names: Vec<String>,
+}
+
+impl VisitMut for ParamNameVisitor {
+  fn visit_type_param_mut(&mut self, node: &mut syn::TypeParam) {
+    self.names.push(node.ident.to_string());
+    syn::visit_mut::visit_type_param_mut(self, node);
+  }
+
+  fn visit_const_param_mut(&mut self, node: &mut syn::ConstParam) {
+    self.names.push(node.ident.to_string());
+    syn::visit_mut::visit_const_param_mut(self, node);
+  }
+}
+
+/// ジェネリクスの引数の名前
+fn param_names(generics: &Generics) -> Vec<String> {
+  let mut visitor = ParamNameVisitor::default();
+  visitor.visit_generics_mut(&mut generics.clone());
+  visitor.names
+}
+
+/// 関数のシグネチャに含まれる型を書き換える
+fn visit_fn_data_mut<V: VisitMut>(visitor: &mut V, fn_data: &mut FnData) {
+  for arg in fn_data.args.iter_mut() {
+    visitor.visit_fn_arg_mut(arg);
+  }
+  visitor.visit_return_type_mut(&mut fn_data.return_type);
+  visitor.visit_generics_mut(&mut fn_data.generics);
+}
+
+/// アイテムに含まれる全ての型を書き換える
+fn visit_item_types_mut<V: VisitMut>(visitor: &mut V, data: &mut ItemTypeData) {
+  match data {
+    ItemTypeData::Const(d) => visitor.visit_type_mut(&mut d.ty),
+    ItemTypeData::Static(d) => visitor.visit_type_mut(&mut d.ty),
+    ItemTypeData::Union(d) => {
+      visitor.visit_generics_mut(&mut d.generics);
+      visitor.visit_fields_mut(&mut d.fields);
+    }
+    ItemTypeData::Type(d) => {
+      visitor.visit_generics_mut(&mut d.generics);
+      visitor.visit_type_mut(&mut d.ty);
+    }
+    ItemTypeData::Struct(d) => {
+      visitor.visit_generics_mut(&mut d.generics);
+      visitor.visit_fields_mut(&mut d.fields);
+    }
+    ItemTypeData::Enum(d) => {
+      visitor.visit_generics_mut(&mut d.generics);
+      for v in d.variants.iter_mut() {
+        visitor.visit_fields_mut(&mut v.fields);
+      }
+    }
+    ItemTypeData::Fn(d) => visit_fn_data_mut(visitor, d),
+    ItemTypeData::Trait(d) => {
+      visitor.visit_generics_mut(&mut d.generics);
+      for bound in d.supertraits.iter_mut() {
+        visitor.visit_type_param_bound_mut(bound);
+      }
+      for item in d.items.iter_mut() {
+        match item {
+          TraitItemData::Const(c) => visitor.visit_type_mut(&mut c.ty),
+          TraitItemData::Type(t) => {
+            visitor.visit_generics_mut(&mut t.generics);
+            for bound in t.bounds.iter_mut() {
+              visitor.visit_type_param_bound_mut(bound);
             }
           }
+          TraitItemData::Fn(f) => visit_fn_data_mut(visitor, &mut f.fn_data),
         }
       }
-      true
     }
-    _ => false,
+    ItemTypeData::Macro(_) => (),
   }
 }
 
-/// 型を探して互換性の有無を判定する
+/// アイテムに含まれる型のパスを正規化する
 ///
-/// TODO:
-/// - attributes
-/// - generics
-/// - impl
-/// - trait
-pub(crate) fn determine_compatibility(
-  old_data: &ItemTypeData,
-  new_data_list: &[ItemTypeData],
-) -> ResultDetermineCompatibility {
-  for new_data in new_data_list.iter() {
-    match (old_data, new_data) {
-      (ItemTypeData::Const(old_const), ItemTypeData::Const(new_const)) => {
-        if old_const.ident == new_const.ident {
-          if *old_const.ty == *new_const.ty {
-            return ResultDetermineCompatibility::Ok;
-          } else {
-            return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Const(
-              new_const.clone(),
-            ));
-          }
+/// `String`と`std::string::String`のように、書き方が違うだけの型を同じものとして比較するため
+pub(crate) fn canonicalize_types(data: &mut ItemTypeData, module_info: &ModuleInfo) {
+  canonicalize_types_in(data, module_info, Vec::new());
+}
+
+/// implの中のアイテムのように、外側のジェネリクスの引数`params`を使えるアイテムの型のパスを正規化する
+fn canonicalize_types_in(data: &mut ItemTypeData, module_info: &ModuleInfo, params: Vec<String>) {
+  let mut param_visitor = ParamNameVisitor { names: params };
+  visit_item_types_mut(&mut param_visitor, data);
+  let mut visitor = CanonicalPathVisitor {
+    module_info,
+    params: param_visitor.names,
+  };
+  visit_item_types_mut(&mut visitor, data);
+}
+
+#[test]
+fn check_canonicalize_raw_ident() {
+  let file: syn::File = syn::parse_quote! {
+    pub mod r#async {
+      pub struct Foo;
+    }
+    use r#async::Foo;
+    pub fn f(x: r#async::Foo, y: Foo) {}
+  };
+  let module_info = ModuleInfo::new(Vec::new(), file.items);
+  let mut data = extract_types(&module_info.items)
+    .into_iter()
+    .find(|d| d.ident() == "f")
+    .unwrap();
+  canonicalize_types(&mut data, &module_info);
+  let ItemTypeData::Fn(fn_data) = data else {
+    panic!("not fn")
+  };
+  let expected: syn::Type = syn::parse_quote!(crate::r#async::Foo);
+  for arg in fn_data.args.iter() {
+    let syn::FnArg::Typed(pat_type) = arg else {
+      panic!("not typed")
+    };
+    assert_eq!(expected, *pat_type.ty);
+  }
+}
+
+#[test]
+fn check_canonicalize_glob_imports() {
+  let canonical_fn = |api: syn::File| {
+    let root: syn::File = syn::parse_quote! {
+      pub mod types;
+      pub mod api;
+    };
+    let types: syn::File = syn::parse_quote! {
+      pub struct Foo;
+    };
+    let mut list = vec![
+      ModuleInfo::new(vec![syn::parse_quote!(types)], types.items),
+      ModuleInfo::new(vec![syn::parse_quote!(api)], api.items),
+      ModuleInfo::new(Vec::new(), root.items),
+    ];
+    file::expand_glob_imports(&mut list);
+    let mut data = extract_types(&list[1].items).remove(0);
+    canonicalize_types(&mut data, &list[1]);
+    data
+  };
+  assert_eq!(
+    canonical_fn(syn::parse_quote! {
+      pub fn f<T>(x: crate::types::Foo, m: std::collections::HashMap<u8, T>) {}
+    }),
+    canonical_fn(syn::parse_quote! {
+      use super::types::*;
+      use std::collections::*;
+      pub fn f<T>(x: Foo, m: HashMap<u8, T>) {}
+    })
+  );
+}
+
+/// クレート内のアイテムへのパスを、利用者から見える公開されたパスに置き換える
+///
+/// アイテムを移動して元の場所から再エクスポートしても、同じ型として比較するため
+struct PublicPathVisitor<'a> {
+  surface: &'a PublicSurface,
+}
+
+impl VisitMut for PublicPathVisitor<'_> {
+  fn visit_path_mut(&mut self, node: &mut syn::Path) {
+    let segments = node
+      .segments
+      .iter()
+      .map(|s| s.ident.to_string())
+      .collect::<Vec<_>>();
+    if let Some(("crate", local_path)) = segments.split_first().map(|(f, r)| (f.as_str(), r)) {
+      if let Some(public_path) = self
+        .surface
+        .def_of(local_path)
+        .and_then(|def| self.surface.public_path_of(&def))
+      {
+        if public_path != local_path {
+          let mut path = vec!["crate".to_string()];
+          path.extend(public_path.iter().cloned());
+          replace_path_segments(node, &path);
         }
       }
-      (ItemTypeData::Static(old_static), ItemTypeData::Static(new_static)) => {
-        if old_static.ident == new_static.ident {
-          if *old_static.ty == *new_static.ty && old_static.is_mut == new_static.is_mut {
-            return ResultDetermineCompatibility::Ok;
-          } else {
-            return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Static(
-              new_static.clone(),
-            ));
-          }
-        }
+    }
+    syn::visit_mut::visit_path_mut(self, node);
+  }
+}
+
+/// アイテムに含まれるクレート内のパスを、公開されたパスに置き換える
+///
+/// 正規化して型エイリアスを展開した後のアイテムに使う
+pub(crate) fn publicize_paths(data: &mut ItemTypeData, surface: &PublicSurface) {
+  visit_item_types_mut(&mut PublicPathVisitor { surface }, data);
+}
+
+/// 型エイリアスの定義
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TypeAliasData {
+  /// `crate`から始まる正規化したパス
+  pub path: Vec<String>,
+  pub generics: Generics,
+  /// 正規化した展開先の型
+  pub ty: Type,
+}
+
+/// クレート全体から型エイリアスを集める
+///
+/// 非公開のエイリアスも公開シグネチャの中で使えるので全て含める
+pub(crate) fn extract_type_aliases(module_info_list: &[ModuleInfo]) -> Vec<TypeAliasData> {
+  let mut v = Vec::new();
+  for module_info in module_info_list.iter() {
+    for item in module_info.items.iter() {
+      if let Item::Type(item_type) = item {
+        let mut path = vec!["crate".to_string()];
+        path.extend(module_info.mod_path.iter().map(|i| i.to_string()));
+        path.push(item_type.ident.to_string());
+        let mut generics = item_type.generics.clone();
+        let mut ty = (*item_type.ty).clone();
+        let mut visitor = CanonicalPathVisitor {
+          module_info,
+          params: param_names(&generics),
+        };
+        visitor.visit_generics_mut(&mut generics);
+        visitor.visit_type_mut(&mut ty);
+        v.push(TypeAliasData { path, generics, ty });
       }
-      (ItemTypeData::Union(old_union), ItemTypeData::Union(new_union)) => {
-        if old_union.ident == new_union.ident {
-          if fileds_eq(&old_union.fields, &new_union.fields) {
-            return ResultDetermineCompatibility::Ok;
-          } else {
-            return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Union(
-              new_union.clone(),
-            ));
-          }
+    }
+  }
+  v
+}
+
+/// 型エイリアスの引数を実際の型に置き換える
+struct AliasArgsVisitor {
+  types: Vec<(Ident, Type)>,
+  consts: Vec<(Ident, syn::Expr)>,
+  lifetimes: Vec<(syn::Lifetime, syn::Lifetime)>,
+}
+
+impl AliasArgsVisitor {
+  fn find_const(&self, path: &syn::Path) -> Option<&syn::Expr> {
+    let ident = path.get_ident()?;
+    self
+      .consts
+      .iter()
+      .find(|(name, _)| name == ident)
+      .map(|(_, expr)| expr)
+  }
+}
+
+impl VisitMut for AliasArgsVisitor {
+  fn visit_expr_mut(&mut self, node: &mut syn::Expr) {
+    if let syn::Expr::Path(expr_path) = node {
+      if expr_path.qself.is_none() {
+        if let Some(expr) = self.find_const(&expr_path.path) {
+          *node = expr.clone();
+          return;
         }
       }
-      (ItemTypeData::Type(old_type), ItemTypeData::Type(new_type)) => {
-        if old_type.ident == new_type.ident {
-          if *old_type.ty == *new_type.ty {
-            return ResultDetermineCompatibility::Ok;
-          } else {
-            return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Type(
-              new_type.clone(),
-            ));
-          }
+    }
+    syn::visit_mut::visit_expr_mut(self, node);
+  }
+
+  fn visit_generic_argument_mut(&mut self, node: &mut syn::GenericArgument) {
+    // `Foo<N>`のconst引数は型の形で書かれる
+    if let syn::GenericArgument::Type(Type::Path(type_path)) = node {
+      if type_path.qself.is_none() {
+        if let Some(expr) = self.find_const(&type_path.path) {
+          *node = syn::GenericArgument::Const(expr.clone());
+          return;
         }
       }
-      (ItemTypeData::Struct(old_struct), ItemTypeData::Struct(new_struct)) => {
-        if old_struct.ident == new_struct.ident {
-          if fileds_eq(&old_struct.fields, &new_struct.fields) {
-            return ResultDetermineCompatibility::Ok;
-          } else {
-            return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Struct(
-              new_struct.clone(),
-            ));
+    }
+    syn::visit_mut::visit_generic_argument_mut(self, node);
+  }
+
+  fn visit_type_mut(&mut self, node: &mut Type) {
+    if let Type::Path(type_path) = node {
+      if type_path.qself.is_none() {
+        if let Some(ident) = type_path.path.get_ident() {
+          if let Some((_, ty)) = self.types.iter().find(|(name, _)| name == ident) {
+            *node = ty.clone();
+            return;
           }
         }
       }
-      (ItemTypeData::Enum(old_enum), ItemTypeData::Enum(new_enum)) => {
-        if old_enum.ident == new_enum.ident {
-          for old_v in old_enum.variants.iter() {
-            if let Some(new_v) = new_enum
-              .variants
-              .iter()
-              .find(|v| v.ident.clone() == old_v.ident)
-            {
-              if !fileds_eq(&old_v.fields, &new_v.fields) {
-                return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Enum(
-                  new_enum.clone(),
-                ));
+    }
+    syn::visit_mut::visit_type_mut(self, node);
+  }
+
+  fn visit_lifetime_mut(&mut self, node: &mut syn::Lifetime) {
+    if let Some((_, lifetime)) = self.lifetimes.iter().find(|(name, _)| name == node) {
+      *node = lifetime.clone();
+    }
+  }
+}
+
+/// 型エイリアスを展開する
+struct AliasExpandVisitor<'a> {
+  aliases: &'a [TypeAliasData],
+  depth: usize,
+}
+
+impl VisitMut for AliasExpandVisitor<'_> {
+  fn visit_type_mut(&mut self, node: &mut Type) {
+    // 循環したエイリアスでも止まるようにする
+    if self.depth > 16 {
+      return;
+    }
+    if let Type::Path(type_path) = node {
+      if type_path.qself.is_none() {
+        let segments = type_path
+          .path
+          .segments
+          .iter()
+          .map(|s| s.ident.to_string())
+          .collect::<Vec<_>>();
+        if let Some(alias) = self.aliases.iter().find(|a| a.path == segments) {
+          // 型引数とconst引数は、ライフタイムとは別に宣言された順に対応する
+          let mut arg_values = Vec::new();
+          let mut arg_lifetimes = Vec::new();
+          if let Some(syn::PathArguments::AngleBracketed(args)) =
+            type_path.path.segments.last().map(|s| &s.arguments)
+          {
+            for arg in args.args.iter() {
+              match arg {
+                syn::GenericArgument::Type(_) | syn::GenericArgument::Const(_) => {
+                  arg_values.push(arg.clone())
+                }
+                syn::GenericArgument::Lifetime(lifetime) => arg_lifetimes.push(lifetime.clone()),
+                _ => (),
               }
-            } else {
-              // not found fields
-              return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Enum(
-                new_enum.clone(),
-              ));
             }
           }
-          return ResultDetermineCompatibility::Ok;
-        }
-      }
-      (ItemTypeData::Fn(old_fn), ItemTypeData::Fn(new_fn)) => {
-        if old_fn.ident == new_fn.ident {
-          let return_ty_eq = match (&old_fn.return_type, &new_fn.return_type) {
-            (ReturnType::Default, ReturnType::Default) => true,
-            (ReturnType::Type(_, old_re_ty), ReturnType::Type(_, new_re_ty)) => {
-              *old_re_ty == *new_re_ty
-            }
-            _ => false,
+          let mut args_visitor = AliasArgsVisitor {
+            types: Vec::new(),
+            consts: Vec::new(),
+            lifetimes: Vec::new(),
           };
-          let old_args_len = old_fn.args.len();
-          let new_args_len = new_fn.args.len();
-          let args_eq = if old_args_len == new_args_len {
-            let args_zip = old_fn.args.iter().zip(new_fn.args.iter());
-            let mut is_eq = true;
-            for (old_arg, new_arg) in args_zip {
-              match (old_arg, new_arg) {
-                (FnArg::Receiver(old_receiver), FnArg::Receiver(new_receiver)) => {
-                  is_eq = old_receiver.reference.is_some() == new_receiver.reference.is_some()
-                    && old_receiver.mutability.is_some() == new_receiver.mutability.is_some()
-                    && old_receiver.ty == new_receiver.ty;
+          let mut value_index = 0;
+          let mut substitutable = true;
+          for param in alias.generics.params.iter() {
+            match param {
+              GenericParam::Type(t) => {
+                let ty = match arg_values.get(value_index) {
+                  Some(syn::GenericArgument::Type(ty)) => Some(ty.clone()),
+                  Some(_) => None,
+                  None => t.default.clone(),
+                };
+                value_index += 1;
+                if let Some(ty) = ty {
+                  args_visitor.types.push((t.ident.clone(), ty));
+                }
+              }
+              GenericParam::Const(c) => {
+                let expr = match arg_values.get(value_index) {
+                  Some(syn::GenericArgument::Const(expr)) => Some(expr.clone()),
+                  Some(syn::GenericArgument::Type(Type::Path(type_path)))
+                    if type_path.qself.is_none() && type_path.path.get_ident().is_some() =>
+                  {
+                    Some(syn::Expr::Path(syn::ExprPath {
+                      attrs: Vec::new(),
+                      qself: None,
+                      path: type_path.path.clone(),
+                    }))
+                  }
+                  Some(_) => None,
+                  None => c.default.clone(),
+                };
+                value_index += 1;
+                match expr {
+                  Some(expr) => args_visitor.consts.push((c.ident.clone(), expr)),
+                  // 値が分からないconst引数は置き換えられないので、エイリアスのまま比較する
+                  None => substitutable = false,
                 }
-                (FnArg::Typed(old_typed), FnArg::Typed(new_typed)) => {
-                  is_eq = old_typed.pat == new_typed.pat && old_typed.ty == new_typed.ty;
+              }
+              GenericParam::Lifetime(l) => {
+                let i = args_visitor.lifetimes.len();
+                if let Some(lifetime) = arg_lifetimes.get(i) {
+                  args_visitor
+                    .lifetimes
+                    .push((l.lifetime.clone(), lifetime.clone()));
                 }
-                _ => is_eq = false,
               }
             }
-            is_eq
-          } else {
-            false
-          };
-          if (old_fn.is_async == new_fn.is_async)
-            && (old_fn.is_const == new_fn.is_const)
-            && return_ty_eq
-            && args_eq
-          {
-            return ResultDetermineCompatibility::Ok;
-          } else {
-            return ResultDetermineCompatibility::Uncompatible(ItemTypeData::Fn(new_fn.clone()));
           }
+          if !substitutable {
+            syn::visit_mut::visit_type_mut(self, node);
+            return;
+          }
+          let mut expanded = alias.ty.clone();
+          args_visitor.visit_type_mut(&mut expanded);
+          *node = expanded;
+          self.depth += 1;
+          self.visit_type_mut(node);
+          self.depth -= 1;
+          return;
         }
       }
-      (ItemTypeData::Macro(old_macro), ItemTypeData::Macro(new_macro)) => {
-        if old_macro.ident == new_macro.ident {
-          return ResultDetermineCompatibility::Ok;
-        }
+    }
+    syn::visit_mut::visit_type_mut(self, node);
+  }
+}
+
+/// アイテムに含まれる型エイリアスを展開する
+///
+/// 正規化した後のアイテムに使う
+pub(crate) fn expand_type_aliases(data: &mut ItemTypeData, aliases: &[TypeAliasData]) {
+  visit_item_types_mut(&mut AliasExpandVisitor { aliases, depth: 0 }, data);
+}
+
+#[test]
+fn check_expand_type_aliases() {
+  let file: syn::File = syn::parse_quote! {
+    pub type Res<T, E = Error> = Result<T, E>;
+    pub type Bytes = Vec<u8>;
+    pub struct Error;
+    pub fn f(x: Bytes) -> Res<Bytes> {}
+  };
+  let module_info = ModuleInfo::new(Vec::new(), file.items);
+  let aliases = extract_type_aliases(std::slice::from_ref(&module_info));
+  let mut data = extract_types(&module_info.items)
+    .into_iter()
+    .find(|d| d.ident() == "f")
+    .unwrap();
+  canonicalize_types(&mut data, &module_info);
+  expand_type_aliases(&mut data, &aliases);
+  let ItemTypeData::Fn(fn_data) = data else {
+    panic!()
+  };
+  let FnArg::Typed(arg) = &fn_data.args[0] else {
+    panic!()
+  };
+  assert_eq!("std::vec::Vec<u8>", show_tokens(&arg.ty));
+  assert_eq!(
+    "std::result::Result<std::vec::Vec<u8>, crate::Error>",
+    show_return_type(&fn_data.return_type)
+  );
+}
+
+#[test]
+fn check_expand_const_alias() {
+  let file: syn::File = syn::parse_quote! {
+    pub type Arr<const N: usize> = [u8; N];
+    pub type Wrap<T, const N: usize> = Vec<Foo<T, N>>;
+    pub struct Foo<T, const N: usize>(T);
+    pub fn f<const M: usize>(a: Arr<3>, b: Arr<M>, c: Wrap<u8, 4>, d: Arr<{ 1 + 1 }>) {}
+  };
+  let module_info = ModuleInfo::new(Vec::new(), file.items);
+  let aliases = extract_type_aliases(std::slice::from_ref(&module_info));
+  let mut data = extract_types(&module_info.items)
+    .into_iter()
+    .find(|d| d.ident() == "f")
+    .unwrap();
+  canonicalize_types(&mut data, &module_info);
+  expand_type_aliases(&mut data, &aliases);
+  let ItemTypeData::Fn(fn_data) = data else {
+    panic!()
+  };
+  let arg_types = fn_data
+    .args
+    .iter()
+    .map(|arg| {
+      let FnArg::Typed(arg) = arg else { panic!() };
+      show_tokens(&arg.ty)
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      "[u8 ; 3]",
+      "[u8 ; M]",
+      "std::vec::Vec<crate::Foo<u8, 4>>",
+      "[u8 ; { 1 + 1 }]"
+    ],
+    arg_types
+  );
+}
+
+/// `impl Trait for Type`の情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TraitImplData {
+  /// 比較に使う、ジェネリクスの引数の名前を位置で置き換えた`impl Trait for Type`の表記
+  pub key: String,
+  /// 表示に使う、書かれた名前のままの`impl Trait for Type`の表記
+  pub name: String,
+  /// traitと型に含まれる、クレート内のアイテムの絶対パス
+  pub local_paths: Vec<Vec<String>>,
+}
+
+impl TraitImplData {
+  /// traitと型の、利用者が使う公開されたパスを求める
+  ///
+  /// 非公開のtraitや型を含む実装は利用者から使えないので`None`を返す
+  pub(crate) fn public_paths<'a>(
+    &self,
+    surface: &'a PublicSurface,
+  ) -> Option<Vec<&'a Vec<String>>> {
+    if self.local_paths.is_empty() {
+      return None;
+    }
+    self
+      .local_paths
+      .iter()
+      .map(|p| {
+        surface
+          .def_of(p)
+          .and_then(|def| surface.public_path_of(&def))
+      })
+      .collect()
+  }
+}
+
+/// 正規化した`impl Trait for Type`の表記を作る
+fn trait_impl_key(
+  item_impl: &syn::ItemImpl,
+  module_info: &ModuleInfo,
+  aliases: &[TypeAliasData],
+  surface: &PublicSurface,
+) -> Option<String> {
+  let (negative, trait_path, _) = item_impl.trait_.as_ref()?;
+  let mut canonical_visitor = CanonicalPathVisitor {
+    module_info,
+    params: param_names(&item_impl.generics),
+  };
+  let mut canonical_trait_path = trait_path.clone();
+  canonicalize_path(
+    module_info,
+    &canonical_visitor.params,
+    &mut canonical_trait_path,
+  );
+  canonical_visitor
+    .visit_path_arguments_mut(&mut canonical_trait_path.segments.last_mut().unwrap().arguments);
+  let mut canonical_self_ty = (*item_impl.self_ty).clone();
+  canonical_visitor.visit_type_mut(&mut canonical_self_ty);
+  let mut alias_visitor = AliasExpandVisitor { aliases, depth: 0 };
+  alias_visitor
+    .visit_path_arguments_mut(&mut canonical_trait_path.segments.last_mut().unwrap().arguments);
+  alias_visitor.visit_type_mut(&mut canonical_self_ty);
+  let mut public_path_visitor = PublicPathVisitor { surface };
+  public_path_visitor.visit_path_mut(&mut canonical_trait_path);
+  public_path_visitor.visit_type_mut(&mut canonical_self_ty);
+  Some(format!(
+    "impl {}{} for {}",
+    if negative.is_some() { "!" } else { "" },
+    show_tokens(&canonical_trait_path),
+    show_tokens(&canonical_self_ty)
+  ))
+}
+
+/// クレート全体からtraitの実装を集める
+pub(crate) fn extract_trait_impls(
+  module_info_list: &[ModuleInfo],
+  aliases: &[TypeAliasData],
+  surface: &PublicSurface,
+) -> Vec<TraitImplData> {
+  let mut v = Vec::new();
+  for module_info in module_info_list.iter() {
+    for item in module_info.items.iter() {
+      let Item::Impl(item_impl) = item else {
+        continue;
+      };
+      let Some(name) = trait_impl_key(item_impl, module_info, aliases, surface) else {
+        continue;
+      };
+      let mut renamed_impl = item_impl.clone();
+      normalize::rename_impl_params_by_position(&mut renamed_impl);
+      let Some(key) = trait_impl_key(&renamed_impl, module_info, aliases, surface) else {
+        continue;
+      };
+      let Some((_, trait_path, _)) = &item_impl.trait_ else {
+        continue;
+      };
+      let mut visitor = LocalPathVisitor {
+        module_info,
+        paths: Vec::new(),
+      };
+      visitor.visit_path(trait_path);
+      visitor.visit_type(&item_impl.self_ty);
+      let segments = trait_path
+        .segments
+        .iter()
+        .map(|s| s.ident.to_string())
+        .collect::<Vec<_>>();
+      if let Some(path) = file::resolve_local_path(module_info, &segments) {
+        visitor.paths.push(path);
       }
-      _ => (),
+      v.push(TraitImplData {
+        key,
+        name,
+        local_paths: visitor.paths,
+      });
+    }
+  }
+  v
+}
+
+#[test]
+fn check_extract_trait_impls() {
+  let old: syn::File = syn::parse_quote! {
+    pub struct W<T>(T);
+    impl<T: Clone> Clone for W<T> {}
+    impl<'a, T> From<&'a T> for W<T> {}
+  };
+  let new: syn::File = syn::parse_quote! {
+    pub struct W<T>(T);
+    impl<U: Clone> Clone for W<U> {}
+    impl<'b, T> From<&'b T> for W<T> {}
+    impl<T> Default for W<T> {}
+  };
+  let keys = |file: syn::File| {
+    let list = vec![ModuleInfo::new(Vec::new(), file.items)];
+    let surface = exports::public_surface(&list);
+    extract_trait_impls(&list, &[], &surface)
+  };
+  let old_impls = keys(old);
+  let new_impls = keys(new);
+  assert_eq!(2, old_impls.len());
+  for old_impl in old_impls.iter() {
+    assert!(new_impls.iter().any(|i| i.key == old_impl.key));
+  }
+  assert_eq!("impl std::clone::Clone for crate::W<T>", old_impls[0].name);
+  assert_eq!("impl std::clone::Clone for crate::W<U>", new_impls[0].name);
+}
+
+#[test]
+fn check_trait_impl_public_paths() {
+  let file: syn::File = syn::parse_quote! {
+    pub struct Pub;
+    struct Priv;
+    pub trait PubTrait {}
+    trait PrivTrait {}
+    impl PrivTrait for Pub {}
+    impl PubTrait for Priv {}
+    impl PubTrait for Pub {}
+    impl Clone for Pub {}
+    impl Clone for Priv {}
+  };
+  let list = vec![ModuleInfo::new(Vec::new(), file.items)];
+  let surface = exports::public_surface(&list);
+  let public = extract_trait_impls(&list, &[], &surface)
+    .into_iter()
+    .filter(|i| i.public_paths(&surface).is_some())
+    .map(|i| i.name)
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      "impl crate::PubTrait for crate::Pub",
+      "impl std::clone::Clone for crate::Pub"
+    ],
+    public
+  );
+}
+
+/// 型の中に現れる`dyn Trait`のうち、クレート内のtraitの絶対パスを集める
+struct DynTraitVisitor<'a> {
+  module_info: &'a ModuleInfo,
+  paths: Vec<Vec<String>>,
+}
+
+impl<'ast> Visit<'ast> for DynTraitVisitor<'_> {
+  fn visit_type_trait_object(&mut self, node: &'ast TypeTraitObject) {
+    for bound in node.bounds.iter() {
+      if let TypeParamBound::Trait(trait_bound) = bound {
+        let segments = trait_bound
+          .path
+          .segments
+          .iter()
+          .map(|s| s.ident.to_string())
+          .collect::<Vec<_>>();
+        if let Some(path) = file::resolve_local_path(self.module_info, &segments) {
+          self.paths.push(path);
+        }
+      }
+    }
+    syn::visit::visit_type_trait_object(self, node);
+  }
+}
+
+/// アイテムの公開シグネチャに`dyn Trait`として現れる、クレート内のtraitの絶対パスを集める
+///
+/// `data`は`module_info`で定義されたものをそのまま渡す
+pub(crate) fn dyn_trait_paths(data: &ItemTypeData, module_info: &ModuleInfo) -> Vec<Vec<String>> {
+  let mut visitor = DynTraitVisitor {
+    module_info,
+    paths: Vec::new(),
+  };
+  match data {
+    ItemTypeData::Const(d) => visitor.visit_type(&d.ty),
+    ItemTypeData::Static(d) => visitor.visit_type(&d.ty),
+    ItemTypeData::Union(d) => visitor.visit_fields(&d.fields),
+    ItemTypeData::Type(d) => visitor.visit_type(&d.ty),
+    ItemTypeData::Struct(d) => visitor.visit_fields(&d.fields),
+    ItemTypeData::Enum(d) => {
+      for v in d.variants.iter() {
+        visitor.visit_fields(&v.fields)
+      }
+    }
+    ItemTypeData::Fn(d) => {
+      for arg in d.args.iter() {
+        visitor.visit_fn_arg(arg)
+      }
+      visitor.visit_return_type(&d.return_type)
+    }
+    ItemTypeData::Trait(d) => {
+      for item in d.items.iter() {
+        if let TraitItemData::Fn(f) = item {
+          for arg in f.fn_data.args.iter() {
+            visitor.visit_fn_arg(arg)
+          }
+          visitor.visit_return_type(&f.fn_data.return_type)
+        }
+      }
+    }
+    ItemTypeData::Macro(_) => (),
+  }
+  visitor.paths
+}
+
+/// traitが`dyn Trait`として使われている場所を記録する
+pub(crate) fn mark_dyn_usage(
+  data_list: &mut [ItemTypeData],
+  module_info: &ModuleInfo,
+  dyn_usage: &[(Vec<String>, String)],
+) {
+  for data in data_list.iter_mut() {
+    if let ItemTypeData::Trait(trait_data) = data {
+      let mut def = exports::mod_path_strings(module_info);
+      def.push(trait_data.ident.to_string());
+      trait_data.dyn_usage = dyn_usage
+        .iter()
+        .filter(|(path, _)| *path == def)
+        .map(|(_, place)| place.clone())
+        .collect();
+    }
+  }
+}
+
+/// 型の中に`Self`そのものや`impl Trait`が現れるかを調べる
+#[derive(Default)]
+struct SelfTypeVisitor {
+  has_self: bool,
+  has_impl_trait: bool,
+}
+
+impl<'ast> Visit<'ast> for SelfTypeVisitor {
+  fn visit_type_path(&mut self, node: &'ast TypePath) {
+    // `Self::Item`のような射影は問題ない
+    if node.qself.is_none() && node.path.is_ident("Self") {
+      self.has_self = true;
+    }
+    syn::visit::visit_type_path(self, node);
+  }
+
+  fn visit_type_impl_trait(&mut self, node: &'ast TypeImplTrait) {
+    self.has_impl_trait = true;
+    syn::visit::visit_type_impl_trait(self, node);
+  }
+}
+
+/// `where Self: Sized`が付いているか
+fn has_self_sized_bound(generics: &Generics) -> bool {
+  generics.where_clause.iter().any(|where_clause| {
+    where_clause
+      .predicates
+      .iter()
+      .any(|predicate| match predicate {
+        WherePredicate::Type(predicate_type) => {
+          show_tokens(&predicate_type.bounded_ty) == "Self"
+            && predicate_type.bounds.iter().any(|bound| match bound {
+              TypeParamBound::Trait(trait_bound) => trait_bound
+                .path
+                .segments
+                .last()
+                .is_some_and(|s| s.ident == "Sized"),
+              _ => false,
+            })
+        }
+        _ => false,
+      })
+  })
+}
+
+/// traitを`dyn Trait`として使えない理由を列挙する
+fn dyn_incompatibilities(trait_data: &TraitData) -> Vec<String> {
+  // `Sized`を要求したり、`Self`を型引数に取ったりする標準ライブラリのtrait
+  const NOT_DYN_COMPATIBLE_TRAITS: [&str; 9] = [
+    "Sized",
+    "Clone",
+    "Copy",
+    "Default",
+    "PartialEq",
+    "Eq",
+    "PartialOrd",
+    "Ord",
+    "Hash",
+  ];
+  let mut reasons = Vec::new();
+  for bound in trait_data.supertraits.iter() {
+    if let TypeParamBound::Trait(trait_bound) = bound {
+      let mut visitor = SelfTypeVisitor::default();
+      visitor.visit_path(&trait_bound.path);
+      let is_std_trait = trait_bound
+        .path
+        .segments
+        .last()
+        .is_some_and(|s| NOT_DYN_COMPATIBLE_TRAITS.contains(&s.ident.to_string().as_str()));
+      if is_std_trait || visitor.has_self {
+        reasons.push(format!(
+          "supertrait `{}` is not dyn-compatible",
+          show_tokens(&trait_bound.path)
+        ));
+      }
+    }
+  }
+  for item in trait_data.items.iter() {
+    match item {
+      TraitItemData::Const(d) => reasons.push(format!("associated const `{}`", d.ident)),
+      TraitItemData::Type(d) => {
+        if !d.generics.params.is_empty() {
+          reasons.push(format!("generic associated type `{}`", d.ident))
+        }
+      }
+      TraitItemData::Fn(d) => {
+        let fn_data = &d.fn_data;
+        if has_self_sized_bound(&fn_data.generics) {
+          continue;
+        }
+        let name = &fn_data.ident;
+        if !matches!(fn_data.args.first(), Some(FnArg::Receiver(_))) {
+          reasons.push(format!("`fn {name}` has no receiver"));
+          continue;
+        }
+        if fn_data
+          .generics
+          .params
+          .iter()
+          .any(|p| !matches!(p, GenericParam::Lifetime(_)))
+        {
+          reasons.push(format!("`fn {name}` has generic parameters"));
+        }
+        let mut args_visitor = SelfTypeVisitor::default();
+        for arg in fn_data.args.iter().skip(1) {
+          args_visitor.visit_fn_arg(arg);
+        }
+        let mut return_visitor = SelfTypeVisitor::default();
+        return_visitor.visit_return_type(&fn_data.return_type);
+        if args_visitor.has_impl_trait {
+          reasons.push(format!("`fn {name}` takes `impl Trait` arguments"));
+        }
+        if args_visitor.has_self || return_visitor.has_self {
+          reasons.push(format!("`fn {name}` uses `Self` in its signature"));
+        }
+        if fn_data.is_async || return_visitor.has_impl_trait {
+          reasons.push(format!("`fn {name}` returns `impl Trait`"));
+        }
+      }
+    }
+  }
+  reasons
+}
+
+#[test]
+fn check_dyn_incompatibilities() {
+  let file: syn::File = syn::parse_quote! {
+    pub trait Ok {
+      fn a(&self) -> u8;
+      fn b(&self) -> Self where Self: Sized;
+    }
+    pub trait Ng: Clone {
+      const C: u8;
+      type G<'a>;
+      fn new() -> u8;
+      fn gen<T>(&self, t: T);
+      fn arg(&self, x: impl Copy);
+      fn same(&self, other: &Self);
+      async fn run(&self);
+    }
+  };
+  let reasons = extract_types(&file.items)
+    .iter()
+    .map(|data| {
+      let ItemTypeData::Trait(trait_data) = data else {
+        panic!()
+      };
+      dyn_incompatibilities(trait_data)
+    })
+    .collect::<Vec<_>>();
+  assert!(reasons[0].is_empty());
+  assert_eq!(
+    vec![
+      "supertrait `Clone` is not dyn-compatible",
+      "associated const `C`",
+      "generic associated type `G`",
+      "`fn new` has no receiver",
+      "`fn gen` has generic parameters",
+      "`fn arg` takes `impl Trait` arguments",
+      "`fn same` uses `Self` in its signature",
+      "`fn run` returns `impl Trait`",
+    ],
+    reasons[1]
+  );
+}
+
+#[test]
+fn check_dyn_trait_paths() {
+  let file: syn::File = syn::parse_quote! {
+    pub mod a {
+      pub trait Error {}
+    }
+    use a::Error;
+    pub struct Holder {
+      pub b: Box<dyn std::error::Error>,
+      pub c: Box<dyn Error + Send>,
+    }
+  };
+  let module_info = ModuleInfo::new(Vec::new(), file.items);
+  let data = extract_types(&module_info.items)
+    .into_iter()
+    .find(|d| d.ident() == "Holder")
+    .unwrap();
+  assert_eq!(
+    vec![vec!["a".to_string(), "Error".to_string()]],
+    dyn_trait_paths(&data, &module_info)
+  );
+}
+
+/// 変更の重大さ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+  /// 利用者からは見えない変更
+  Info,
+  /// 後方互換性のある追加
+  Minor,
+  /// 後方互換性のない変更
+  Breaking,
+}
+
+/// 検出した変更点
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Change {
+  pub severity: Severity,
+  pub message: String,
+}
+
+impl Change {
+  pub(crate) fn breaking(message: impl Into<String>) -> Self {
+    Change {
+      severity: Severity::Breaking,
+      message: message.into(),
+    }
+  }
+
+  pub(crate) fn minor(message: impl Into<String>) -> Self {
+    Change {
+      severity: Severity::Minor,
+      message: message.into(),
+    }
+  }
+
+  pub(crate) fn info(message: impl Into<String>) -> Self {
+    Change {
+      severity: Severity::Info,
+      message: message.into(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ResultDetermineCompatibility {
+  Ok,
+  /// 互換性はあるが報告すべき変更がある
+  Compatible(Vec<Change>),
+  Uncompatible(Box<ItemTypeData>, Vec<Change>),
+  NotFound,
+}
+
+impl ResultDetermineCompatibility {
+  fn from_changes(new_data: &ItemTypeData, changes: Vec<Change>) -> Self {
+    if changes.iter().any(|c| c.severity == Severity::Breaking) {
+      Self::Uncompatible(Box::new(new_data.clone()), changes)
+    } else if changes.is_empty() {
+      Self::Ok
+    } else {
+      Self::Compatible(changes)
+    }
+  }
+}
+
+/// 表示用にトークン列を文字列にする
+pub(crate) fn show_tokens<T: ToTokens>(t: &T) -> String {
+  t.to_token_stream()
+    .to_string()
+    .replace(" :: ", "::")
+    .replace(":: ", "::")
+    .replace("& ", "&")
+    .replace(" <", "<")
+    .replace("< ", "<")
+    .replace(" >", ">")
+    .replace(" ,", ",")
+    .replace("? ", "?")
+}
+
+/// フィールドの一覧を名前（タプルの場合は位置）と組にする
+fn named_fields(fields: &Fields) -> Vec<(String, &syn::Field)> {
+  fields
+    .iter()
+    .enumerate()
+    .map(|(i, f)| match &f.ident {
+      Some(ident) => (format!("`{ident}`"), f),
+      None => (i.to_string(), f),
+    })
+    .collect()
+}
+
+/// 下流のコードから構造体リテラルで構築できるか
+///
+/// 全てのフィールドが公開されていて、`#[non_exhaustive]`が付いていない場合に限られる
+fn is_constructible(fields: &Fields, attrs: &[Attribute]) -> bool {
+  !attrs::is_non_exhaustive(attrs)
+    && fields
+      .iter()
+      .all(|f| matches!(f.vis, Visibility::Public(_)))
+}
+
+/// 構造体や共用体のフィールドを比較する
+///
+/// 非公開のフィールドは利用者から見えないので、構築できるかどうかにだけ影響する
+fn fields_changes(
+  old_fields: &Fields,
+  new_fields: &Fields,
+  old_constructible: bool,
+) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let is_same_kind = matches!(
+    (old_fields, new_fields),
+    (Fields::Unit, Fields::Unit)
+      | (Fields::Named(_), Fields::Named(_))
+      | (Fields::Unnamed(_), Fields::Unnamed(_))
+  );
+  let old_has_pub_field = old_fields
+    .iter()
+    .any(|f| matches!(f.vis, Visibility::Public(_)));
+  if !is_same_kind && (old_constructible || old_has_pub_field) {
+    changes.push(Change::breaking("kind of fields changed"));
+    return changes;
+  }
+  let old_named_fields = named_fields(old_fields);
+  let new_named_fields = named_fields(new_fields);
+  for (name, old_f) in old_named_fields.iter() {
+    let Visibility::Public(_) = old_f.vis else {
+      continue;
+    };
+    match new_named_fields.iter().find(|(n, _)| n == name) {
+      Some((_, new_f)) => {
+        if !matches!(new_f.vis, Visibility::Public(_)) {
+          changes.push(Change::breaking(format!(
+            "field {name} is no longer public"
+          )));
+        } else if old_f.ty != new_f.ty {
+          changes.push(Change::breaking(format!(
+            "type of field {name} changed: {} -> {}",
+            show_tokens(&old_f.ty),
+            show_tokens(&new_f.ty)
+          )));
+        }
+      }
+      None => changes.push(Change::breaking(format!("field {name} was removed"))),
+    }
+  }
+  for (name, new_f) in new_named_fields.iter() {
+    let old_f = old_named_fields.iter().find(|(n, _)| n == name);
+    let is_pub = matches!(new_f.vis, Visibility::Public(_));
+    match old_f {
+      Some((_, old_f)) => {
+        if is_pub && !matches!(old_f.vis, Visibility::Public(_)) {
+          changes.push(Change::minor(format!("field {name} became public")));
+        }
+      }
+      None => {
+        if old_constructible {
+          // 構造体リテラルや`..`の無いパターンが壊れる
+          changes.push(Change::breaking(format!(
+            "field {name} was added: downstream struct literals and patterns stop compiling"
+          )));
+        } else if is_pub {
+          changes.push(Change::minor(format!("public field {name} was added")));
+        }
+      }
+    }
+  }
+  changes
+}
+
+/// テスト用に最初のアイテム同士を比較し、変更の重大さとメッセージを返す
+#[cfg(test)]
+fn item_changes_of(old: syn::File, new: syn::File) -> Vec<(Severity, String)> {
+  let old_data = extract_types(&old.items);
+  let new_data = extract_types(&new.items);
+  let changes = match determine_compatibility(&old_data[0], &new_data) {
+    ResultDetermineCompatibility::Ok => Vec::new(),
+    ResultDetermineCompatibility::Compatible(changes) => changes,
+    ResultDetermineCompatibility::Uncompatible(_, changes) => changes,
+    ResultDetermineCompatibility::NotFound => panic!("not found"),
+  };
+  changes
+    .into_iter()
+    .map(|c| (c.severity, c.message))
+    .collect()
+}
+
+#[test]
+fn check_fields_changes() {
+  // 全てのフィールドが公開されている構造体は下流で構築できる
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "field `b` was added: downstream struct literals and patterns stop compiling".to_string()
+    )],
+    item_changes_of(
+      syn::parse_quote!(
+        pub struct S {
+          pub a: u8,
+        }
+      ),
+      syn::parse_quote!(
+        pub struct S {
+          pub a: u8,
+          b: u8,
+        }
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "field `a` is no longer public".to_string()
+    )],
+    item_changes_of(
+      syn::parse_quote!(
+        pub struct S {
+          pub a: u8,
+        }
+      ),
+      syn::parse_quote!(
+        pub struct S {
+          pub(crate) a: u8,
+        }
+      )
+    )
+  );
+  // 非公開のフィールドを持つタプル構造体は下流で構築できないので、非公開のフィールドは自由に変えられる
+  assert_eq!(
+    Vec::<(Severity, String)>::new(),
+    item_changes_of(
+      syn::parse_quote!(
+        pub struct T(pub u8, u8);
+      ),
+      syn::parse_quote!(
+        pub struct T(pub u8, u16, u32);
+      )
+    )
+  );
+  assert_eq!(
+    vec![(Severity::Minor, "public field 2 was added".to_string())],
+    item_changes_of(
+      syn::parse_quote!(
+        pub struct T(pub u8, u8);
+      ),
+      syn::parse_quote!(
+        pub struct T(pub u8, u8, pub u8);
+      )
+    )
+  );
+}
+
+/// `#[non_exhaustive]`の付け外しを調べる
+fn non_exhaustive_changes(
+  old_attrs: &[Attribute],
+  new_attrs: &[Attribute],
+  old_constructible: bool,
+) -> Vec<Change> {
+  let mut changes = Vec::new();
+  match (
+    attrs::is_non_exhaustive(old_attrs),
+    attrs::is_non_exhaustive(new_attrs),
+  ) {
+    (false, true) if old_constructible => changes.push(Change::breaking(
+      "became `#[non_exhaustive]`: downstream struct literals and patterns stop compiling",
+    )),
+    (true, false) => changes.push(Change::minor("is no longer `#[non_exhaustive]`")),
+    _ => (),
+  }
+  changes
+}
+
+/// `#[derive(...)]`の変化を調べる
+fn derive_changes(old_attrs: &[Attribute], new_attrs: &[Attribute]) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let old_derives = attrs::derived_traits(old_attrs);
+  let new_derives = attrs::derived_traits(new_attrs);
+  for old_derive in old_derives.iter() {
+    if !new_derives.contains(old_derive) {
+      changes.push(Change::breaking(format!(
+        "derived `{old_derive}` was removed"
+      )));
+    }
+  }
+  for new_derive in new_derives.iter() {
+    if !old_derives.contains(new_derive) {
+      changes.push(Change::minor(format!("derived `{new_derive}` was added")));
+    }
+  }
+  changes
+}
+
+#[test]
+fn check_derive_changes() {
+  assert_eq!(
+    vec![
+      (
+        Severity::Breaking,
+        "derived `Clone` was removed".to_string()
+      ),
+      (Severity::Minor, "derived `PartialEq` was added".to_string())
+    ],
+    item_changes_of(
+      syn::parse_quote!(
+        #[derive(Clone, Debug)]
+        pub struct S;
+      ),
+      syn::parse_quote!(
+        #[derive(Debug, PartialEq)]
+        pub struct S;
+      )
+    )
+  );
+}
+
+/// enumのヴァリアントのフィールドを比較する
+///
+/// ヴァリアントのフィールドは常にenumと同じ可視性を持つので、全てのフィールドを比較する
+fn variant_fields_changes(old_v: &Variant, new_v: &Variant) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let name = &old_v.ident;
+  // `#[non_exhaustive]`なヴァリアントは下流で構築できず、パターンも`..`を必要とする
+  let can_add_fields = attrs::is_non_exhaustive(&old_v.attrs);
+  match (&old_v.fields, &new_v.fields) {
+    (Fields::Unit, Fields::Unit) => (),
+    (Fields::Unnamed(old_unnamed), Fields::Unnamed(new_unnamed)) => {
+      let old_fields = old_unnamed.unnamed.iter().collect::<Vec<_>>();
+      let new_fields = new_unnamed.unnamed.iter().collect::<Vec<_>>();
+      for (i, old_f) in old_fields.iter().enumerate() {
+        match new_fields.get(i) {
+          Some(new_f) => {
+            if old_f.ty != new_f.ty {
+              changes.push(Change::breaking(format!(
+                "type of field {i} of variant `{name}` changed: {} -> {}",
+                show_tokens(&old_f.ty),
+                show_tokens(&new_f.ty)
+              )));
+            }
+          }
+          None => changes.push(Change::breaking(format!(
+            "field {i} of variant `{name}` was removed"
+          ))),
+        }
+      }
+      for i in old_fields.len()..new_fields.len() {
+        if can_add_fields {
+          changes.push(Change::minor(format!(
+            "field {i} was added to non-exhaustive variant `{name}`"
+          )));
+        } else {
+          changes.push(Change::breaking(format!(
+            "field {i} was added to variant `{name}`"
+          )));
+        }
+      }
+    }
+    (Fields::Named(old_named), Fields::Named(new_named)) => {
+      for old_f in old_named.named.iter() {
+        let old_ident = old_f.ident.as_ref().unwrap();
+        match new_named
+          .named
+          .iter()
+          .find(|f| f.ident.as_ref() == Some(old_ident))
+        {
+          Some(new_f) => {
+            if old_f.ty != new_f.ty {
+              changes.push(Change::breaking(format!(
+                "type of field `{old_ident}` of variant `{name}` changed: {} -> {}",
+                show_tokens(&old_f.ty),
+                show_tokens(&new_f.ty)
+              )));
+            }
+          }
+          None => changes.push(Change::breaking(format!(
+            "field `{old_ident}` of variant `{name}` was removed"
+          ))),
+        }
+      }
+      for new_f in new_named.named.iter() {
+        let new_ident = new_f.ident.as_ref().unwrap();
+        if old_named
+          .named
+          .iter()
+          .any(|f| f.ident.as_ref() == Some(new_ident))
+        {
+          continue;
+        }
+        if can_add_fields {
+          changes.push(Change::minor(format!(
+            "field `{new_ident}` was added to non-exhaustive variant `{name}`"
+          )));
+        } else {
+          changes.push(Change::breaking(format!(
+            "field `{new_ident}` was added to variant `{name}`"
+          )));
+        }
+      }
+    }
+    _ => changes.push(Change::breaking(format!(
+      "kind of variant `{name}` changed"
+    ))),
+  }
+  if !attrs::is_non_exhaustive(&old_v.attrs) && attrs::is_non_exhaustive(&new_v.attrs) {
+    changes.push(Change::breaking(format!(
+      "variant `{name}` became `#[non_exhaustive]`"
+    )));
+  }
+  changes
+}
+
+/// enumのヴァリアントを比較する
+fn enum_variants_changes(old_enum: &EnumData, new_enum: &EnumData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let old_non_exhaustive = attrs::is_non_exhaustive(&old_enum.attrs);
+  let new_non_exhaustive = attrs::is_non_exhaustive(&new_enum.attrs);
+  match (old_non_exhaustive, new_non_exhaustive) {
+    (false, true) => changes.push(Change::breaking(
+      "enum became `#[non_exhaustive]`: downstream exhaustive `match` stops compiling",
+    )),
+    (true, false) => changes.push(Change::minor("enum is no longer `#[non_exhaustive]`")),
+    _ => (),
+  }
+  for old_v in old_enum.variants.iter() {
+    if let Some(new_v) = new_enum.variants.iter().find(|v| v.ident == old_v.ident) {
+      changes.extend(variant_fields_changes(old_v, new_v));
+    } else {
+      changes.push(Change::breaking(format!(
+        "variant `{}` was removed",
+        old_v.ident
+      )));
+    }
+  }
+  for new_v in new_enum.variants.iter() {
+    if old_enum.variants.iter().any(|v| v.ident == new_v.ident) {
+      continue;
+    }
+    if old_non_exhaustive {
+      changes.push(Change::minor(format!(
+        "variant `{}` was added to non-exhaustive enum",
+        new_v.ident
+      )));
+    } else {
+      changes.push(Change::breaking(format!(
+        "variant `{}` was added: downstream exhaustive `match` stops compiling",
+        new_v.ident
+      )));
+    }
+  }
+  changes
+}
+
+#[test]
+fn check_enum_variants_changes() {
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "variant `B` was added: downstream exhaustive `match` stops compiling".to_string()
+    )],
+    item_changes_of(
+      syn::parse_quote!(
+        pub enum E {
+          A,
+        }
+      ),
+      syn::parse_quote!(
+        pub enum E {
+          A,
+          B,
+        }
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Minor,
+      "variant `B` was added to non-exhaustive enum".to_string()
+    )],
+    item_changes_of(
+      syn::parse_quote!(
+        #[non_exhaustive]
+        pub enum E {
+          A,
+        }
+      ),
+      syn::parse_quote!(
+        #[non_exhaustive]
+        pub enum E {
+          A,
+          B,
+        }
+      )
+    )
+  );
+  assert_eq!(
+    vec![
+      (
+        Severity::Breaking,
+        "type of field `x` of variant `A` changed: u8 -> u16".to_string()
+      ),
+      (
+        Severity::Breaking,
+        "field `y` of variant `A` was removed".to_string()
+      ),
+      (
+        Severity::Breaking,
+        "field `z` was added to variant `A`".to_string()
+      ),
+    ],
+    item_changes_of(
+      syn::parse_quote!(
+        pub enum E {
+          A { x: u8, y: u8 },
+        }
+      ),
+      syn::parse_quote!(
+        pub enum E {
+          A { x: u16, z: u8 },
+        }
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Minor,
+      "field `z` was added to non-exhaustive variant `A`".to_string()
+    )],
+    item_changes_of(
+      syn::parse_quote!(
+        pub enum E {
+          #[non_exhaustive]
+          A { x: u8 },
+        }
+      ),
+      syn::parse_quote!(
+        pub enum E {
+          #[non_exhaustive]
+          A { x: u8, z: u8 },
+        }
+      )
+    )
+  );
+}
+
+/// `unsafe`や`const`などの関数の修飾子を比較する
+///
+/// 呼び出し側ができることが減る方向の変更だけが非互換になる
+fn fn_qualifier_changes(old_fn: &FnData, new_fn: &FnData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let name = &old_fn.ident;
+  match (old_fn.is_unsafe, new_fn.is_unsafe) {
+    (false, true) => changes.push(Change::breaking(format!(
+      "`fn {name}` became `unsafe`: callers now need an `unsafe` block"
+    ))),
+    (true, false) => changes.push(Change::minor(format!("`fn {name}` is no longer `unsafe`"))),
+    _ => (),
+  }
+  match (old_fn.is_const, new_fn.is_const) {
+    (true, false) => changes.push(Change::breaking(format!(
+      "`fn {name}` is no longer `const`: calls in const contexts stop compiling"
+    ))),
+    (false, true) => changes.push(Change::minor(format!("`fn {name}` became `const`"))),
+    _ => (),
+  }
+  if old_fn.is_async != new_fn.is_async {
+    changes.push(Change::breaking(if new_fn.is_async {
+      format!("`fn {name}` became `async`")
+    } else {
+      format!("`fn {name}` is no longer `async`")
+    }));
+  }
+  if old_fn.abi != new_fn.abi {
+    changes.push(Change::breaking(format!(
+      "ABI of `fn {name}` changed: \"{}\" -> \"{}\"",
+      old_fn.abi, new_fn.abi
+    )));
+  }
+  if old_fn.is_variadic != new_fn.is_variadic {
+    changes.push(Change::breaking(if new_fn.is_variadic {
+      format!("`fn {name}` became variadic")
+    } else {
+      format!("`fn {name}` is no longer variadic")
+    }));
+  }
+  changes
+}
+
+#[test]
+fn check_fn_qualifier_changes() {
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "`fn f` became `unsafe`: callers now need an `unsafe` block".to_string()
+    )],
+    fn_changes_of(
+      syn::parse_quote!(
+        pub fn f() {}
+      ),
+      syn::parse_quote!(
+        pub unsafe fn f() {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(Severity::Minor, "`fn f` is no longer `unsafe`".to_string())],
+    fn_changes_of(
+      syn::parse_quote!(
+        pub unsafe fn f() {}
+      ),
+      syn::parse_quote!(
+        pub fn f() {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "`fn f` is no longer `const`: calls in const contexts stop compiling".to_string()
+    )],
+    fn_changes_of(
+      syn::parse_quote!(
+        pub const fn f() {}
+      ),
+      syn::parse_quote!(
+        pub fn f() {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(Severity::Minor, "`fn f` became `const`".to_string())],
+    fn_changes_of(
+      syn::parse_quote!(
+        pub fn f() {}
+      ),
+      syn::parse_quote!(
+        pub const fn f() {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "ABI of `fn f` changed: \"C\" -> \"system\"".to_string()
+    )],
+    fn_changes_of(
+      syn::parse_quote!(
+        pub extern "C" fn f() {}
+      ),
+      syn::parse_quote!(
+        pub extern "system" fn f() {}
+      )
+    )
+  );
+}
+
+/// 引数のパターンから、`mut`を除いた表記を得る
+fn show_arg_pat(pat: &Pat) -> String {
+  match pat {
+    Pat::Ident(pat_ident) => {
+      let mut pat_ident = pat_ident.clone();
+      pat_ident.mutability = None;
+      show_tokens(&pat_ident)
+    }
+    _ => show_tokens(pat),
+  }
+}
+
+/// 引数の名前の変更を情報として集める
+fn arg_rename_changes(old_fn: &FnData, new_fn: &FnData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  for (i, (old_arg, new_arg)) in old_fn.args.iter().zip(new_fn.args.iter()).enumerate() {
+    if let (FnArg::Typed(old_typed), FnArg::Typed(new_typed)) = (old_arg, new_arg) {
+      let old_pat = show_arg_pat(&old_typed.pat);
+      let new_pat = show_arg_pat(&new_typed.pat);
+      if old_pat != new_pat {
+        changes.push(Change::info(format!(
+          "argument {} of `fn {}` was renamed: `{old_pat}` -> `{new_pat}`",
+          i + 1,
+          old_fn.ident
+        )));
+      }
+    }
+  }
+  changes
+}
+
+#[test]
+fn check_arg_rename_changes() {
+  assert_eq!(
+    vec![(
+      Severity::Info,
+      "argument 1 of `fn f` was renamed: `x` -> `value`".to_string()
+    )],
+    fn_changes_of(
+      syn::parse_quote!(
+        pub fn f(x: u8) {}
+      ),
+      syn::parse_quote!(
+        pub fn f(value: u8) {}
+      )
+    )
+  );
+  // `mut`は関数の中だけの話なので変更にならない
+  assert_eq!(
+    Vec::<(Severity, String)>::new(),
+    fn_changes_of(
+      syn::parse_quote!(
+        pub fn f(x: u8) {}
+      ),
+      syn::parse_quote!(
+        pub fn f(mut x: u8) {}
+      )
+    )
+  );
+}
+
+/// 戻り値の型を表示用の文字列にする
+fn show_return_type(return_type: &ReturnType) -> String {
+  match return_type {
+    ReturnType::Default => "()".to_string(),
+    ReturnType::Type(_, ty) => show_tokens(ty),
+  }
+}
+
+/// 引数と戻り値の型を比較する
+///
+/// 引数ごとに、どの位置がどう変わったかを報告する
+fn fn_args_changes(old_fn: &FnData, new_fn: &FnData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let name = &old_fn.ident;
+  let show_arg = |arg: &FnArg| match arg {
+    FnArg::Receiver(receiver) => show_tokens(receiver),
+    FnArg::Typed(typed) => show_tokens(&typed.ty),
+  };
+  let len = old_fn.args.len().max(new_fn.args.len());
+  for i in 0..len {
+    let position = i + 1;
+    match (old_fn.args.get(i), new_fn.args.get(i)) {
+      (Some(FnArg::Receiver(old_receiver)), Some(FnArg::Receiver(new_receiver))) => {
+        // `mut self`の`mut`は呼び出し側から見えないので、型だけを比較する
+        if old_receiver.ty != new_receiver.ty {
+          changes.push(Change::breaking(format!(
+            "receiver of `fn {name}` changed: {} -> {}",
+            show_tokens(old_receiver),
+            show_tokens(new_receiver)
+          )));
+        }
+      }
+      (Some(FnArg::Typed(old_typed)), Some(FnArg::Typed(new_typed))) => {
+        // 引数の名前やパターンは呼び出し側から見えない
+        if old_typed.ty != new_typed.ty {
+          changes.push(Change::breaking(format!(
+            "argument {position} of `fn {name}` changed: {} -> {}",
+            show_tokens(&old_typed.ty),
+            show_tokens(&new_typed.ty)
+          )));
+        }
+      }
+      (Some(old_arg), Some(new_arg)) => changes.push(Change::breaking(format!(
+        "argument {position} of `fn {name}` changed: {} -> {}",
+        show_arg(old_arg),
+        show_arg(new_arg)
+      ))),
+      (Some(old_arg), None) => changes.push(Change::breaking(format!(
+        "argument {position} of `fn {name}` was removed: {}",
+        show_arg(old_arg)
+      ))),
+      (None, Some(new_arg)) => changes.push(Change::breaking(format!(
+        "argument {position} of `fn {name}` was added: {}",
+        show_arg(new_arg)
+      ))),
+      (None, None) => (),
+    }
+  }
+  let old_return = show_return_type(&old_fn.return_type);
+  let new_return = show_return_type(&new_fn.return_type);
+  if old_return != new_return {
+    changes.push(Change::breaking(format!(
+      "return type of `fn {name}` changed: {old_return} -> {new_return}"
+    )));
+  }
+  changes
+}
+
+#[test]
+fn check_fn_args_changes() {
+  let old_fn: syn::ItemFn = syn::parse_quote!(
+    fn f(x: &str, y: u8) {}
+  );
+  let new_fn: syn::ItemFn = syn::parse_quote!(
+    fn f(x: String, y: u8) {}
+  );
+  let changes = fn_args_changes(
+    &fn_data_from_sig(&[], &old_fn.sig),
+    &fn_data_from_sig(&[], &new_fn.sig),
+  );
+  assert_eq!(
+    vec![Change::breaking(
+      "argument 1 of `fn f` changed: &str -> String"
+    )],
+    changes
+  );
+}
+
+/// `impl Future<Output = T>`の`T`と、`Future`以外の境界を取り出す
+fn impl_future_output(return_type: &ReturnType) -> Option<(Type, Vec<TypeParamBound>)> {
+  let ReturnType::Type(_, ty) = return_type else {
+    return None;
+  };
+  let Type::ImplTrait(impl_trait) = &**ty else {
+    return None;
+  };
+  let mut output = None;
+  let mut others = Vec::new();
+  for bound in impl_trait.bounds.iter() {
+    let future_output = match bound {
+      TypeParamBound::Trait(trait_bound) => trait_bound
+        .path
+        .segments
+        .last()
+        .filter(|segment| segment.ident == "Future")
+        .and_then(|segment| match &segment.arguments {
+          syn::PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            syn::GenericArgument::AssocType(assoc) if assoc.ident == "Output" => {
+              Some(assoc.ty.clone())
+            }
+            _ => None,
+          }),
+          _ => None,
+        }),
+      _ => None,
+    };
+    match future_output {
+      Some(ty) => output = Some(ty),
+      None => others.push(bound.clone()),
+    }
+  }
+  output.map(|ty| (ty, others))
+}
+
+/// `async fn`の戻り値の型にする
+fn async_return_type(output: Type) -> ReturnType {
+  match &output {
+    Type::Tuple(tuple) if tuple.elems.is_empty() => ReturnType::Default,
+    _ => ReturnType::Type(Default::default(), Box::new(output)),
+  }
+}
+
+/// `async fn`と`impl Future`を返す関数を同じ形に揃え、その判断の理由を返す
+///
+/// `Output`が同じなら呼び出し側から見た違いは無い
+fn async_equivalence(old_fn: &mut FnData, new_fn: &mut FnData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let name = old_fn.ident.clone();
+  if old_fn.is_async && !new_fn.is_async {
+    let Some((output, _)) = impl_future_output(&new_fn.return_type) else {
+      return changes;
+    };
+    if show_tokens(&output) != show_return_type(&old_fn.return_type) {
+      return changes;
+    }
+    changes.push(Change::info(format!(
+      "`fn {name}` changed from `async fn` to returning `impl Future<Output = {}>`: callers are unaffected",
+      show_tokens(&output)
+    )));
+    new_fn.is_async = true;
+    new_fn.return_type = async_return_type(output);
+  } else if !old_fn.is_async && new_fn.is_async {
+    let Some((output, others)) = impl_future_output(&old_fn.return_type) else {
+      return changes;
+    };
+    if show_tokens(&output) != show_return_type(&new_fn.return_type) {
+      return changes;
+    }
+    changes.push(Change::info(format!(
+      "`fn {name}` changed from returning `impl Future<Output = {}>` to `async fn`",
+      show_tokens(&output)
+    )));
+    for bound in others.iter() {
+      // `async fn`の返すfutureは、関数の中身によってしか`Send`などを満たさない
+      if show_tokens(bound) != "'_" {
+        changes.push(Change::breaking(format!(
+          "the future returned by `fn {name}` no longer promises `{}`: `async fn` does not state it in the signature",
+          show_tokens(bound)
+        )));
+      }
+    }
+    old_fn.is_async = true;
+    old_fn.return_type = async_return_type(output);
+  }
+  changes
+}
+
+/// 型の中に指定した名前の型引数が現れる回数を数える
+struct ParamUsageVisitor<'a> {
+  name: &'a Ident,
+  count: usize,
+}
+
+impl<'ast> Visit<'ast> for ParamUsageVisitor<'_> {
+  fn visit_type_path(&mut self, node: &'ast TypePath) {
+    if node.qself.is_none() && node.path.is_ident(self.name) {
+      self.count += 1;
+    }
+    syn::visit::visit_type_path(self, node);
+  }
+}
+
+/// 1か所の引数にだけ使われている型引数を、引数位置の`impl Trait`に書き換える
+///
+/// 書き換えられた場合は、型引数の宣言と境界を取り除き、`true`を返す
+fn param_to_impl_trait(fn_data: &mut FnData, index: usize) -> bool {
+  let Some(FnArg::Typed(typed)) = fn_data.args.get(index) else {
+    return false;
+  };
+  let Type::Path(type_path) = &*typed.ty else {
+    return false;
+  };
+  let Some(name) = type_path.path.get_ident().cloned() else {
+    return false;
+  };
+  let Some(param_index) = fn_data
+    .generics
+    .params
+    .iter()
+    .position(|p| matches!(p, GenericParam::Type(t) if t.ident == name))
+  else {
+    return false;
+  };
+
+  // 型引数の宣言と、その型引数自身への境界を取り除いたジェネリクス
+  let mut generics = fn_data.generics.clone();
+  let mut bounds = Punctuated::<TypeParamBound, syn::Token![+]>::new();
+  generics.params = generics
+    .params
+    .into_iter()
+    .enumerate()
+    .filter_map(|(i, p)| {
+      if i == param_index {
+        if let GenericParam::Type(t) = &p {
+          bounds.extend(t.bounds.iter().cloned());
+        }
+        None
+      } else {
+        Some(p)
+      }
+    })
+    .collect();
+  if let Some(where_clause) = &mut generics.where_clause {
+    where_clause.predicates = where_clause
+      .predicates
+      .clone()
+      .into_iter()
+      .filter(|predicate| match predicate {
+        WherePredicate::Type(pt)
+          if pt.lifetimes.is_none() && name == show_tokens(&pt.bounded_ty) =>
+        {
+          bounds.extend(pt.bounds.iter().cloned());
+          false
+        }
+        _ => true,
+      })
+      .collect();
+  }
+  if bounds.is_empty() {
+    return false;
+  }
+
+  // 他の場所で使われている型引数は`impl Trait`にできない
+  let mut visitor = ParamUsageVisitor {
+    name: &name,
+    count: 0,
+  };
+  for arg in fn_data.args.iter() {
+    visitor.visit_fn_arg(arg);
+  }
+  visitor.visit_return_type(&fn_data.return_type);
+  visitor.visit_generics(&generics);
+  if visitor.count != 1 {
+    return false;
+  }
+
+  fn_data.generics = generics;
+  if let Some(FnArg::Typed(typed)) = fn_data.args.get_mut(index) {
+    *typed.ty = Type::ImplTrait(TypeImplTrait {
+      impl_token: Default::default(),
+      bounds,
+    });
+  }
+  true
+}
+
+/// 名前付きの型引数と引数位置の`impl Trait`を同じ形に揃え、その判断の理由を返す
+///
+/// 引数位置の`impl Trait`はturbofishで指定できないので、名前付きの型引数から変えると非互換になる
+fn impl_trait_arg_equivalence(old_fn: &mut FnData, new_fn: &mut FnData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let name = old_fn.ident.clone();
+  let is_impl_trait_arg = |fn_data: &FnData, i: usize| matches!(fn_data.args.get(i), Some(FnArg::Typed(typed)) if matches!(*typed.ty, Type::ImplTrait(_)));
+  let explicit_params = |fn_data: &FnData| {
+    fn_data
+      .generics
+      .params
+      .iter()
+      .filter(|p| !matches!(p, GenericParam::Lifetime(_)))
+      .count()
+  };
+  for i in 0..old_fn.args.len().min(new_fn.args.len()) {
+    let position = i + 1;
+    if is_impl_trait_arg(new_fn, i) && !is_impl_trait_arg(old_fn, i) {
+      if param_to_impl_trait(old_fn, i) {
+        changes.push(Change::breaking(format!(
+          "argument {position} of `fn {name}` changed from a named generic parameter to `impl Trait`: turbofish calls such as `{name}::<..>()` stop compiling"
+        )));
+      }
+    } else if is_impl_trait_arg(old_fn, i) && !is_impl_trait_arg(new_fn, i) {
+      let old_explicit_params = explicit_params(old_fn);
+      if param_to_impl_trait(new_fn, i) {
+        if old_explicit_params == 0 {
+          changes.push(Change::minor(format!(
+            "argument {position} of `fn {name}` changed from `impl Trait` to a named generic parameter: turbofish calls become possible"
+          )));
+        } else {
+          changes.push(Change::breaking(format!(
+            "argument {position} of `fn {name}` changed from `impl Trait` to a named generic parameter: turbofish calls must now specify one more parameter"
+          )));
+        }
+      }
+    }
+  }
+  changes
+}
+
+/// テスト用に2つの関数のシグネチャを比較し、変更の重大さとメッセージを返す
+#[cfg(test)]
+fn fn_changes_of(old_fn: syn::ItemFn, new_fn: syn::ItemFn) -> Vec<(Severity, String)> {
+  fn_changes(
+    &fn_data_from_sig(&old_fn.attrs, &old_fn.sig),
+    &fn_data_from_sig(&new_fn.attrs, &new_fn.sig),
+  )
+  .into_iter()
+  .map(|c| (c.severity, c.message))
+  .collect()
+}
+
+#[test]
+fn check_async_equivalence() {
+  let severities = |old_fn, new_fn| {
+    fn_changes_of(old_fn, new_fn)
+      .into_iter()
+      .map(|(severity, _)| severity)
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    vec![Severity::Info],
+    severities(
+      syn::parse_quote!(
+        async fn f() -> u8 {}
+      ),
+      syn::parse_quote!(
+        fn f() -> impl Future<Output = u8> {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![Severity::Info],
+    severities(
+      syn::parse_quote!(
+        async fn f() -> u8 {}
+      ),
+      syn::parse_quote!(
+        fn f() -> impl Future<Output = u8> + Send {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![Severity::Info],
+    severities(
+      syn::parse_quote!(
+        fn f() -> impl Future<Output = u8> {}
+      ),
+      syn::parse_quote!(
+        async fn f() -> u8 {}
+      )
+    )
+  );
+  // `async fn`にすると`Send`を約束しなくなる
+  assert_eq!(
+    vec![Severity::Info, Severity::Breaking],
+    severities(
+      syn::parse_quote!(
+        fn f() -> impl Future<Output = u8> + Send {}
+      ),
+      syn::parse_quote!(
+        async fn f() -> u8 {}
+      )
+    )
+  );
+  // `Output`が違えば揃えない
+  assert!(severities(
+    syn::parse_quote!(
+      async fn f() -> u8 {}
+    ),
+    syn::parse_quote!(
+      fn f() -> impl Future<Output = u16> {}
+    )
+  )
+  .contains(&Severity::Breaking));
+}
+
+#[test]
+fn check_impl_trait_arg_equivalence() {
+  let changes = fn_changes_of(
+    syn::parse_quote!(
+      fn f<T: Clone>(x: T) {}
+    ),
+    syn::parse_quote!(
+      fn f(x: impl Clone) {}
+    ),
+  );
+  assert_eq!(1, changes.len());
+  assert_eq!(Severity::Breaking, changes[0].0);
+  assert!(changes[0].1.contains("turbofish"));
+
+  // `where`節の境界も`impl Trait`の境界になる
+  let changes = fn_changes_of(
+    syn::parse_quote!(
+      fn f<T>(x: T)
+      where
+        T: Clone + Send,
+      {
+      }
+    ),
+    syn::parse_quote!(
+      fn f(x: impl Clone + Send) {}
+    ),
+  );
+  assert_eq!(1, changes.len());
+  assert_eq!(Severity::Breaking, changes[0].0);
+
+  // 他に明示的な型引数が無ければ、turbofishを使えるようになるだけ
+  let changes = fn_changes_of(
+    syn::parse_quote!(
+      fn f(x: impl Clone) {}
+    ),
+    syn::parse_quote!(
+      fn f<T: Clone>(x: T) {}
+    ),
+  );
+  assert_eq!(1, changes.len());
+  assert_eq!(Severity::Minor, changes[0].0);
+
+  // 他に明示的な型引数があれば、turbofishで指定する数が変わる
+  let changes = fn_changes_of(
+    syn::parse_quote!(
+      fn f<U: Copy>(u: U, x: impl Clone) {}
+    ),
+    syn::parse_quote!(
+      fn f<U: Copy, T: Clone>(u: U, x: T) {}
+    ),
+  );
+  assert_eq!(1, changes.len());
+  assert_eq!(Severity::Breaking, changes[0].0);
+  assert!(changes[0].1.contains("one more parameter"));
+}
+
+#[test]
+fn check_param_used_twice() {
+  let item: syn::ItemFn = syn::parse_quote!(
+    fn f<T: Clone>(x: T, y: T) {}
+  );
+  let mut fn_data = fn_data_from_sig(&item.attrs, &item.sig);
+  let before = fn_data.clone();
+  assert!(!param_to_impl_trait(&mut fn_data, 0));
+  assert_eq!(before, fn_data);
+
+  let item: syn::ItemFn = syn::parse_quote!(
+    fn f<T: Clone>(x: T) -> T {}
+  );
+  let mut fn_data = fn_data_from_sig(&item.attrs, &item.sig);
+  assert!(!param_to_impl_trait(&mut fn_data, 0));
+
+  let changes = fn_changes_of(
+    syn::parse_quote!(
+      fn f<T: Clone>(x: T, y: T) {}
+    ),
+    syn::parse_quote!(
+      fn f(x: impl Clone, y: impl Clone) {}
+    ),
+  );
+  assert!(changes
+    .iter()
+    .all(|(_, message)| !message.contains("turbofish")));
+  assert!(changes
+    .iter()
+    .any(|(severity, _)| *severity == Severity::Breaking));
+}
+
+/// 関数のシグネチャを比較する
+fn fn_changes(old_fn: &FnData, new_fn: &FnData) -> Vec<Change> {
+  // 糖衣構文の違いを揃えてから比較する
+  let mut old_fn = old_fn.clone();
+  let mut new_fn = new_fn.clone();
+  let mut changes = async_equivalence(&mut old_fn, &mut new_fn);
+  changes.extend(impl_trait_arg_equivalence(&mut old_fn, &mut new_fn));
+  normalize::rename_params_like(&old_fn.generics, &mut new_fn);
+  changes.extend(fn_qualifier_changes(&old_fn, &new_fn));
+  changes.extend(fn_args_changes(&old_fn, &new_fn));
+  changes.extend(arg_rename_changes(&old_fn, &new_fn));
+  changes.extend(generics::generics_changes(
+    &old_fn.generics,
+    &new_fn.generics,
+  ));
+  changes
+}
+
+/// traitの宣言部分を比較する
+///
+/// `unsafe`や`auto`、supertraitやジェネリクスの変更は下流の`impl`を壊す
+fn trait_header_changes(old_trait: &TraitData, new_trait: &TraitData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  let name = &old_trait.ident;
+  match (old_trait.is_unsafe, new_trait.is_unsafe) {
+    (false, true) => changes.push(Change::breaking(format!(
+      "trait became `unsafe`: downstream `impl {name} for ..` must now be written as `unsafe impl`"
+    ))),
+    (true, false) => changes.push(Change::breaking(format!(
+      "trait is no longer `unsafe`: downstream `unsafe impl {name} for ..` stops compiling"
+    ))),
+    _ => (),
+  }
+  match (old_trait.is_auto, new_trait.is_auto) {
+    (false, true) => changes.push(Change::breaking(format!(
+      "trait became `auto`: downstream `impl {name} for ..` may conflict with the automatic impls"
+    ))),
+    (true, false) => changes.push(Change::breaking(format!(
+      "trait is no longer `auto`: types that relied on the automatic impl no longer implement `{name}`"
+    ))),
+    _ => (),
+  }
+
+  let old_supertraits = old_trait
+    .supertraits
+    .iter()
+    .map(show_tokens)
+    .collect::<Vec<_>>();
+  let new_supertraits = new_trait
+    .supertraits
+    .iter()
+    .map(show_tokens)
+    .collect::<Vec<_>>();
+  for new_bound in new_supertraits.iter() {
+    if !old_supertraits.contains(new_bound) {
+      changes.push(Change::breaking(format!(
+        "supertrait `{new_bound}` was added: downstream `impl {name} for T` stops compiling unless `T: {new_bound}`"
+      )));
+    }
+  }
+  for old_bound in old_supertraits.iter() {
+    if !new_supertraits.contains(old_bound) {
+      changes.push(Change::breaking(format!(
+        "supertrait `{old_bound}` was removed: code relying on `T: {name}` implying `T: {old_bound}` stops compiling"
+      )));
+    }
+  }
+
+  for change in generics::generics_changes(&old_trait.generics, &new_trait.generics) {
+    if change.severity == Severity::Breaking {
+      changes.push(Change::breaking(format!(
+        "{}: downstream impls of `{name}` must be updated",
+        change.message
+      )));
+    } else {
+      changes.push(change);
+    }
+  }
+  changes
+}
+
+#[test]
+fn check_trait_header_changes() {
+  let changes_of = |old: syn::ItemTrait, new: syn::ItemTrait| {
+    let old_data = extract_types(&[Item::Trait(old)]);
+    let new_data = extract_types(&[Item::Trait(new)]);
+    let (ItemTypeData::Trait(old_trait), ItemTypeData::Trait(new_trait)) =
+      (&old_data[0], &new_data[0])
+    else {
+      panic!("not trait")
+    };
+    trait_header_changes(old_trait, new_trait)
+      .into_iter()
+      .map(|c| (c.severity, c.message))
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "trait became `unsafe`: downstream `impl Tr for ..` must now be written as `unsafe impl`"
+        .to_string()
+    )],
+    changes_of(
+      syn::parse_quote!(
+        pub trait Tr {}
+      ),
+      syn::parse_quote!(
+        pub unsafe trait Tr {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "trait is no longer `unsafe`: downstream `unsafe impl Tr for ..` stops compiling".to_string()
+    )],
+    changes_of(
+      syn::parse_quote!(
+        pub unsafe trait Tr {}
+      ),
+      syn::parse_quote!(
+        pub trait Tr {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "supertrait `Send` was added: downstream `impl Tr for T` stops compiling unless `T: Send`"
+        .to_string()
+    )],
+    changes_of(
+      syn::parse_quote!(
+        pub trait Tr: Clone {}
+      ),
+      syn::parse_quote!(
+        pub trait Tr: Clone + Send {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "supertrait `Send` was removed: code relying on `T: Tr` implying `T: Send` stops compiling"
+        .to_string()
+    )],
+    changes_of(
+      syn::parse_quote!(
+        pub trait Tr: Clone + Send {}
+      ),
+      syn::parse_quote!(
+        pub trait Tr: Clone {}
+      )
+    )
+  );
+  // 既定値の無い引数を増やすと、利用者の`impl Tr<A> for ..`を書き直す必要がある
+  assert_eq!(
+    vec![(
+      Severity::Breaking,
+      "generic parameter `B` was added without a default: downstream impls of `Tr` must be updated"
+        .to_string()
+    )],
+    changes_of(
+      syn::parse_quote!(
+        pub trait Tr<A> {}
+      ),
+      syn::parse_quote!(
+        pub trait Tr<A, B> {}
+      )
+    )
+  );
+  assert_eq!(
+    vec![(
+      Severity::Minor,
+      "generic parameter `B = u8` with default was added".to_string()
+    )],
+    changes_of(
+      syn::parse_quote!(
+        pub trait Tr<A> {}
+      ),
+      syn::parse_quote!(
+        pub trait Tr<A, B = u8> {}
+      )
+    )
+  );
+}
+
+/// `dyn Trait`として使えなくなったかを調べる
+fn trait_dyn_compatibility_changes(old_trait: &TraitData, new_trait: &TraitData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  if !dyn_incompatibilities(old_trait).is_empty() {
+    return changes;
+  }
+  let reasons = dyn_incompatibilities(new_trait);
+  if reasons.is_empty() {
+    return changes;
+  }
+  let name = &old_trait.ident;
+  changes.push(Change::breaking(format!(
+    "trait is no longer dyn-compatible ({}): downstream `dyn {name}` stops compiling",
+    reasons.join(", ")
+  )));
+  if !old_trait.dyn_usage.is_empty() {
+    // 公開APIで`dyn Trait`として使っているので、確実に利用者に影響する
+    changes.push(Change::breaking(format!(
+      "`dyn {name}` is part of the public API: {}",
+      old_trait.dyn_usage.join(", ")
+    )));
+  }
+  changes
+}
+
+/// traitの関連アイテムを比較する
+///
+/// 実装する側から見て、要求されるものが増えたり変わったりすると非互換になる
+fn trait_items_changes(old_trait: &TraitData, new_trait: &TraitData) -> Vec<Change> {
+  let mut changes = Vec::new();
+  for old_item in old_trait.items.iter() {
+    let Some(new_item) = new_trait
+      .items
+      .iter()
+      .find(|i| i.ident() == old_item.ident() && i.show_name() == old_item.show_name())
+    else {
+      changes.push(Change::breaking(format!(
+        "`{}` was removed",
+        old_item.show_name()
+      )));
+      continue;
+    };
+    match (old_item, new_item) {
+      (TraitItemData::Const(old_const), TraitItemData::Const(new_const))
+        if old_const.ty != new_const.ty =>
+      {
+        changes.push(Change::breaking(format!(
+          "type of `const {}` changed: {} -> {}",
+          old_const.ident,
+          show_tokens(&old_const.ty),
+          show_tokens(&new_const.ty)
+        )));
+      }
+      (TraitItemData::Type(old_type), TraitItemData::Type(new_type))
+        if old_type.bounds != new_type.bounds =>
+      {
+        changes.push(Change::breaking(format!(
+          "bounds of `type {}` changed: `{}` -> `{}`",
+          old_type.ident,
+          show_tokens(&old_type.bounds),
+          show_tokens(&new_type.bounds)
+        )));
+      }
+      (TraitItemData::Fn(old_fn), TraitItemData::Fn(new_fn)) => {
+        for mut change in fn_changes(&old_fn.fn_data, &new_fn.fn_data) {
+          // 呼び出し側で問題ない変更でも、下流の実装は元のシグネチャのまま書かれている
+          if change.severity == Severity::Minor && !old_trait.is_sealed {
+            change = Change::breaking(format!(
+              "{}: existing implementations of the trait no longer match",
+              change.message
+            ));
+          }
+          changes.push(change);
+        }
+      }
+      _ => (),
+    }
+    if old_item.has_default() && !new_item.has_default() && !old_trait.is_sealed {
+      changes.push(Change::breaking(format!(
+        "default of `{}` was removed",
+        old_item.show_name()
+      )));
+    }
+  }
+  for new_item in new_trait.items.iter() {
+    if !old_trait
+      .items
+      .iter()
+      .any(|i| i.ident() == new_item.ident() && i.show_name() == new_item.show_name())
+    {
+      if new_item.has_default() {
+        changes.push(Change::minor(format!(
+          "`{}` with default was added",
+          new_item.show_name()
+        )));
+      } else if old_trait.is_sealed {
+        // sealed traitは下流で実装されていないので、要求が増えても壊れない
+        changes.push(Change::minor(format!(
+          "required `{}` was added to sealed trait",
+          new_item.show_name()
+        )));
+      } else {
+        changes.push(Change::breaking(format!(
+          "required `{}` was added",
+          new_item.show_name()
+        )));
+      }
+    }
+  }
+  changes
+}
+
+#[test]
+fn check_trait_items_changes() {
+  let old: syn::File = syn::parse_quote! {
+    pub trait Foo {
+      fn a(&self);
+      fn b(&self);
+    }
+  };
+  let new: syn::File = syn::parse_quote! {
+    pub trait Foo {
+      fn a(&self);
+      fn c(&self) {}
+      fn d(&self);
+    }
+  };
+  let old_data = extract_types(&old.items);
+  let new_data = extract_types(&new.items);
+  let (ItemTypeData::Trait(old_trait), ItemTypeData::Trait(new_trait)) =
+    (&old_data[0], &new_data[0])
+  else {
+    panic!()
+  };
+  let severities = trait_items_changes(old_trait, new_trait)
+    .iter()
+    .map(|c| c.severity)
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![Severity::Breaking, Severity::Minor, Severity::Breaking],
+    severities
+  );
+}
+
+#[test]
+fn check_trait_fn_signature_changes() {
+  let old: syn::File = syn::parse_quote! {
+    pub trait Foo {
+      fn f<X: Clone + Send>(x: X);
+      unsafe fn g(&self) {}
+    }
+  };
+  let new: syn::File = syn::parse_quote! {
+    pub trait Foo {
+      fn f<X: Clone>(x: X);
+      fn g(&self) {}
+    }
+  };
+  let old_data = extract_types(&old.items);
+  let new_data = extract_types(&new.items);
+  let (ItemTypeData::Trait(old_trait), ItemTypeData::Trait(new_trait)) =
+    (&old_data[0], &new_data[0])
+  else {
+    panic!()
+  };
+  let severities = |old_trait: &TraitData| {
+    trait_items_changes(old_trait, new_trait)
+      .iter()
+      .map(|c| c.severity)
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    vec![Severity::Breaking, Severity::Breaking],
+    severities(old_trait)
+  );
+  // sealed traitは下流で実装されないので、呼び出し側から見て判定する
+  let mut sealed_trait = old_trait.clone();
+  sealed_trait.is_sealed = true;
+  assert_eq!(
+    vec![Severity::Minor, Severity::Minor],
+    severities(&sealed_trait)
+  );
+}
+
+/// 型を探して互換性の有無を判定する
+pub(crate) fn determine_compatibility(
+  old_data: &ItemTypeData,
+  new_data_list: &[ItemTypeData],
+) -> ResultDetermineCompatibility {
+  let Some(new_data) = new_data_list
+    .iter()
+    .find(|d| d.is_same_kind(old_data) && d.ident() == old_data.ident())
+  else {
+    return ResultDetermineCompatibility::NotFound;
+  };
+  let mut renamed_data = new_data.clone();
+  normalize::rename_item_params_like(old_data, &mut renamed_data);
+  let mut changes = Vec::new();
+  match (old_data, &renamed_data) {
+    (ItemTypeData::Const(old_const), ItemTypeData::Const(new_const))
+      if *old_const.ty != *new_const.ty =>
+    {
+      changes.push(Change::breaking(format!(
+        "type changed: {} -> {}",
+        show_tokens(&old_const.ty),
+        show_tokens(&new_const.ty)
+      )));
+    }
+    (ItemTypeData::Static(old_static), ItemTypeData::Static(new_static)) => {
+      if *old_static.ty != *new_static.ty {
+        changes.push(Change::breaking(format!(
+          "type changed: {} -> {}",
+          show_tokens(&old_static.ty),
+          show_tokens(&new_static.ty)
+        )));
+      }
+      if old_static.is_mut != new_static.is_mut {
+        changes.push(Change::breaking("mutability changed"));
+      }
+    }
+    (ItemTypeData::Union(old_union), ItemTypeData::Union(new_union)) => {
+      changes.extend(generics::generics_changes(
+        &old_union.generics,
+        &new_union.generics,
+      ));
+      // 共用体のリテラルは1つのフィールドしか指定しないので、フィールドが増えても壊れない
+      changes.extend(fields_changes(&old_union.fields, &new_union.fields, false));
+      changes.extend(derive_changes(&old_union.attrs, &new_union.attrs));
+    }
+    (ItemTypeData::Type(old_type), ItemTypeData::Type(new_type)) => {
+      changes.extend(generics::generics_changes(
+        &old_type.generics,
+        &new_type.generics,
+      ));
+      if *old_type.ty != *new_type.ty {
+        changes.push(Change::breaking(format!(
+          "type changed: {} -> {}",
+          show_tokens(&old_type.ty),
+          show_tokens(&new_type.ty)
+        )));
+      }
+    }
+    (ItemTypeData::Struct(old_struct), ItemTypeData::Struct(new_struct)) => {
+      changes.extend(generics::generics_changes(
+        &old_struct.generics,
+        &new_struct.generics,
+      ));
+      let old_constructible = is_constructible(&old_struct.fields, &old_struct.attrs);
+      changes.extend(fields_changes(
+        &old_struct.fields,
+        &new_struct.fields,
+        old_constructible,
+      ));
+      changes.extend(non_exhaustive_changes(
+        &old_struct.attrs,
+        &new_struct.attrs,
+        old_constructible,
+      ));
+      changes.extend(derive_changes(&old_struct.attrs, &new_struct.attrs));
+    }
+    (ItemTypeData::Enum(old_enum), ItemTypeData::Enum(new_enum)) => {
+      changes.extend(generics::generics_changes(
+        &old_enum.generics,
+        &new_enum.generics,
+      ));
+      changes.extend(derive_changes(&old_enum.attrs, &new_enum.attrs));
+      changes.extend(enum_variants_changes(old_enum, new_enum));
+    }
+    (ItemTypeData::Fn(old_fn), ItemTypeData::Fn(new_fn)) => {
+      changes.extend(fn_changes(old_fn, new_fn));
+    }
+    (ItemTypeData::Trait(old_trait), ItemTypeData::Trait(new_trait)) => {
+      changes.extend(trait_header_changes(old_trait, new_trait));
+      changes.extend(trait_dyn_compatibility_changes(old_trait, new_trait));
+      changes.extend(trait_items_changes(old_trait, new_trait));
+    }
+    _ => (),
+  }
+  ResultDetermineCompatibility::from_changes(new_data, changes)
+}
+
+#[test]
+fn check_renamed_type_params() {
+  let old: syn::File = syn::parse_quote! {
+    pub struct S<T>(pub T);
+    pub enum E<'a> { A(&'a str) }
+    pub union U<T: Copy> { pub a: T }
+    pub type A<T> = Vec<T>;
+    pub trait Tr<T>: AsRef<T> {
+      const C: Option<T>;
+      type A: Into<T>;
+      fn f(&self, x: T) -> T;
+    }
+  };
+  let new: syn::File = syn::parse_quote! {
+    pub struct S<U>(pub U);
+    pub enum E<'b> { A(&'b str) }
+    pub union U<X: Copy> { pub a: X }
+    pub type A<X> = Vec<X>;
+    pub trait Tr<U>: AsRef<U> {
+      const C: Option<U>;
+      type A: Into<U>;
+      fn f(&self, x: U) -> U;
     }
+  };
+  let new_data = extract_types(&new.items);
+  for old_data in extract_types(&old.items).iter() {
+    assert_eq!(
+      ResultDetermineCompatibility::Ok,
+      determine_compatibility(old_data, &new_data),
+      "{}",
+      old_data.show_name()
+    );
   }
-  ResultDetermineCompatibility::NotFound
 }